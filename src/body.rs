@@ -0,0 +1,235 @@
+use bytes::Bytes;
+use std::fmt;
+use std::io::{self, Error, ErrorKind};
+use std::pin::Pin;
+use tokio::fs::File;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+// Size of the buffer used to move streamed bodies from their source to the socket
+const STREAM_CHUNK_SIZE: usize = 8 * 1024;
+
+// Content of a response. Only `Full` is held in memory, the other variants are copied to the
+// socket piece by piece as the client accepts them.
+pub enum Body {
+    Full(Bytes),
+    // Reader of a possibly unknown length, sent with chunked encoding when the length is unknown
+    Stream {
+        reader: Pin<Box<dyn AsyncRead + Send>>,
+        length: Option<u64>,
+    },
+    File {
+        file: File,
+        length: u64,
+    },
+}
+
+impl Body {
+    pub fn stream<R>(reader: R, length: Option<u64>) -> Self
+    where
+        R: AsyncRead + Send + 'static,
+    {
+        Body::Stream {
+            reader: Box::pin(reader),
+            length,
+        }
+    }
+
+    pub async fn file(file: File) -> io::Result<Self> {
+        let length = file.metadata().await?.len();
+        Ok(Body::File { file, length })
+    }
+
+    // Number of bytes the body will produce, if known up front
+    pub fn len(&self) -> Option<u64> {
+        match self {
+            Body::Full(bytes) => Some(bytes.len() as u64),
+            Body::Stream { length, .. } => *length,
+            Body::File { length, .. } => Some(*length),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == Some(0)
+    }
+
+    // In-memory content, None for streamed bodies
+    pub fn as_bytes(&self) -> Option<&Bytes> {
+        match self {
+            Body::Full(bytes) => Some(bytes),
+            _ => None,
+        }
+    }
+
+    // Copy the body to `writer`. With `chunked` set the content is framed with the chunked
+    // transfer coding, otherwise it is written as-is and a known length is enforced so the
+    // Content-Length that was already sent stays truthful.
+    pub async fn write_to<W>(self, writer: &mut W, chunked: bool) -> io::Result<()>
+    where
+        W: AsyncWrite + Unpin,
+    {
+        let length = self.len();
+        let mut reader: Pin<Box<dyn AsyncRead + Send>> = match self {
+            Body::Full(bytes) => {
+                if chunked {
+                    write_chunk(writer, &bytes).await?;
+                    writer.write_all(b"0\r\n\r\n").await?;
+                } else {
+                    writer.write_all(&bytes).await?;
+                }
+                return Ok(());
+            }
+            Body::Stream { reader, .. } => reader,
+            Body::File { file, .. } => Box::pin(file),
+        };
+
+        let mut buffer = vec![0; STREAM_CHUNK_SIZE];
+        let mut written: u64 = 0;
+        loop {
+            // never read past the announced length, a growing file must not corrupt the framing
+            let to_read = match length {
+                Some(length) if !chunked => {
+                    (length - written).min(STREAM_CHUNK_SIZE as u64) as usize
+                }
+                _ => STREAM_CHUNK_SIZE,
+            };
+            if to_read == 0 {
+                break;
+            }
+            let bytes_read = reader.read(&mut buffer[..to_read]).await?;
+            if bytes_read == 0 {
+                break;
+            }
+            if chunked {
+                write_chunk(writer, &buffer[..bytes_read]).await?;
+            } else {
+                writer.write_all(&buffer[..bytes_read]).await?;
+            }
+            written += bytes_read as u64;
+        }
+
+        if chunked {
+            writer.write_all(b"0\r\n\r\n").await?;
+        } else if let Some(length) = length {
+            if written < length {
+                // the client was promised more bytes than the source produced
+                return Err(Error::new(
+                    ErrorKind::UnexpectedEof,
+                    format!("body ended after {} of {} bytes", written, length),
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+async fn write_chunk<W>(writer: &mut W, data: &[u8]) -> io::Result<()>
+where
+    W: AsyncWrite + Unpin,
+{
+    if data.is_empty() {
+        // a zero-sized chunk would terminate the body early
+        return Ok(());
+    }
+    writer
+        .write_all(format!("{:x}\r\n", data.len()).as_bytes())
+        .await?;
+    writer.write_all(data).await?;
+    writer.write_all(b"\r\n").await
+}
+
+impl From<Bytes> for Body {
+    fn from(bytes: Bytes) -> Self {
+        Body::Full(bytes)
+    }
+}
+
+impl From<String> for Body {
+    fn from(text: String) -> Self {
+        Body::Full(Bytes::from(text))
+    }
+}
+
+impl From<&'static str> for Body {
+    fn from(text: &'static str) -> Self {
+        Body::Full(Bytes::from_static(text.as_bytes()))
+    }
+}
+
+impl From<Vec<u8>> for Body {
+    fn from(bytes: Vec<u8>) -> Self {
+        Body::Full(Bytes::from(bytes))
+    }
+}
+
+impl fmt::Debug for Body {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Body::Full(bytes) => write!(f, "Body::Full({} bytes)", bytes.len()),
+            Body::Stream { length, .. } => write!(f, "Body::Stream({:?} bytes)", length),
+            Body::File { length, .. } => write!(f, "Body::File({} bytes)", length),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_write_full_body() {
+        let mut output = Vec::new();
+        Body::from("hello")
+            .write_to(&mut output, false)
+            .await
+            .unwrap();
+        assert_eq!(output, b"hello");
+
+        let mut output = Vec::new();
+        Body::from("hello")
+            .write_to(&mut output, true)
+            .await
+            .unwrap();
+        assert_eq!(output, b"5\r\nhello\r\n0\r\n\r\n");
+    }
+
+    #[tokio::test]
+    async fn test_write_stream_with_known_length() {
+        let data = vec![7u8; STREAM_CHUNK_SIZE * 2 + 10];
+        let body = Body::stream(std::io::Cursor::new(data.clone()), Some(data.len() as u64));
+
+        let mut output = Vec::new();
+        body.write_to(&mut output, false).await.unwrap();
+        assert_eq!(output, data);
+    }
+
+    #[tokio::test]
+    async fn test_write_stream_stops_at_length() {
+        let body = Body::stream(&b"0123456789"[..], Some(4));
+        let mut output = Vec::new();
+        body.write_to(&mut output, false).await.unwrap();
+        assert_eq!(output, b"0123");
+    }
+
+    #[tokio::test]
+    async fn test_write_short_stream_is_an_error() {
+        let body = Body::stream(&b"abc"[..], Some(10));
+        let mut output = Vec::new();
+        let error = body.write_to(&mut output, false).await.unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::UnexpectedEof);
+    }
+
+    #[tokio::test]
+    async fn test_write_stream_chunked() {
+        let data = vec![1u8; STREAM_CHUNK_SIZE + 3];
+        let body = Body::stream(std::io::Cursor::new(data), None);
+        assert_eq!(body.len(), None);
+
+        let mut output = Vec::new();
+        body.write_to(&mut output, true).await.unwrap();
+
+        let mut expected = format!("{:x}\r\n", STREAM_CHUNK_SIZE).into_bytes();
+        expected.extend(vec![1u8; STREAM_CHUNK_SIZE]);
+        expected.extend_from_slice(b"\r\n3\r\n\x01\x01\x01\r\n0\r\n\r\n");
+        assert_eq!(output, expected);
+    }
+}
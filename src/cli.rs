@@ -9,3 +9,7 @@ pub fn get_cli_arg_by_name(arg_name: &str) -> Option<String> {
     }
     None
 }
+
+pub fn has_cli_flag(flag_name: &str) -> bool {
+    env::args().any(|arg| arg == flag_name)
+}
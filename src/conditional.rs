@@ -0,0 +1,375 @@
+use crate::date::{format_http_date, parse_http_date};
+use crate::http::{HTTPStatus, HeaderMap, Method};
+use crate::request::RequestHeaders;
+use crate::response::HTTPResponse;
+
+use std::fmt;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Opaque validator of a representation, `"abc"` or weak `W/"abc"`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EntityTag {
+    pub weak: bool,
+    pub tag: String,
+}
+
+impl EntityTag {
+    pub fn strong(tag: impl Into<String>) -> Self {
+        EntityTag {
+            weak: false,
+            tag: tag.into(),
+        }
+    }
+
+    pub fn weak(tag: impl Into<String>) -> Self {
+        EntityTag {
+            weak: true,
+            tag: tag.into(),
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        let value = value.trim();
+        let (weak, quoted) = match value.strip_prefix("W/") {
+            Some(rest) => (true, rest),
+            None => (false, value),
+        };
+        let tag = quoted.strip_prefix('"')?.strip_suffix('"')?;
+        // etagc is any visible character except the double quote
+        if !tag
+            .bytes()
+            .all(|b| b == 0x21 || (0x23..=0x7e).contains(&b) || b >= 0x80)
+        {
+            return None;
+        }
+        Some(EntityTag {
+            weak,
+            tag: tag.to_string(),
+        })
+    }
+
+    // Both tags are strong and identical, required for If-Match and range requests
+    pub fn strong_eq(&self, other: &EntityTag) -> bool {
+        !self.weak && !other.weak && self.tag == other.tag
+    }
+
+    // Identical apart from weakness, used for If-None-Match
+    pub fn weak_eq(&self, other: &EntityTag) -> bool {
+        self.tag == other.tag
+    }
+}
+
+impl fmt::Display for EntityTag {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.weak {
+            write!(f, "W/")?;
+        }
+        write!(f, "\"{}\"", self.tag)
+    }
+}
+
+// Value of If-Match or If-None-Match: `*` or a list of entity tags
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum TagCondition {
+    Any,
+    Tags(Vec<EntityTag>),
+}
+
+impl TagCondition {
+    // All values of the header, None if it is absent or contains no valid tag
+    fn from_headers(headers: &HeaderMap, name: &str) -> Option<Self> {
+        let values: Vec<&str> = headers
+            .get_all(name)
+            .flat_map(|value| value.split(','))
+            .map(str::trim)
+            .filter(|value| !value.is_empty())
+            .collect();
+        if values.is_empty() {
+            return None;
+        }
+        if values.contains(&"*") {
+            return Some(TagCondition::Any);
+        }
+        let tags: Vec<EntityTag> = values.into_iter().filter_map(EntityTag::parse).collect();
+        if tags.is_empty() {
+            return None;
+        }
+        Some(TagCondition::Tags(tags))
+    }
+
+    fn matches(&self, current: Option<&EntityTag>, strong: bool) -> bool {
+        match (self, current) {
+            (TagCondition::Any, _) => current.is_some(),
+            (TagCondition::Tags(_), None) => false,
+            (TagCondition::Tags(tags), Some(current)) => tags.iter().any(|tag| {
+                if strong {
+                    tag.strong_eq(current)
+                } else {
+                    tag.weak_eq(current)
+                }
+            }),
+        }
+    }
+}
+
+// Validators of the current representation of a resource
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Validators {
+    pub etag: Option<EntityTag>,
+    pub last_modified: Option<SystemTime>,
+}
+
+impl Validators {
+    // ETag and Last-Modified headers describing the representation
+    pub fn add_to(&self, headers: &mut HeaderMap) {
+        if let Some(etag) = &self.etag {
+            headers.insert("ETag", &etag.to_string());
+        }
+        if let Some(last_modified) = self.last_modified {
+            headers.insert("Last-Modified", &format_http_date(last_modified));
+        }
+    }
+
+    // Dates in headers have a resolution of one second
+    fn last_modified_secs(&self) -> Option<u64> {
+        self.last_modified
+            .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+            .map(|duration| duration.as_secs())
+    }
+}
+
+fn header_date_secs(headers: &HeaderMap, name: &str) -> Option<u64> {
+    let date = parse_http_date(headers.get(name)?)?;
+    date.duration_since(UNIX_EPOCH).ok().map(|d| d.as_secs())
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Precondition {
+    // carry on with the request
+    Proceed,
+    // the client's cached copy is current, answer 304
+    NotModified,
+    // the resource is not in the state the client expects, answer 412
+    Failed,
+}
+
+// Evaluate the conditional headers of a request against the current state of the resource,
+// `current` is None when the resource does not exist. Follows the order of RFC 9110 13.2.2:
+// If-Match, If-Unmodified-Since, If-None-Match, then If-Modified-Since.
+pub fn evaluate(request: &RequestHeaders, current: Option<&Validators>) -> Precondition {
+    let headers = &request.headers;
+    let is_read = matches!(request.method, Method::Get | Method::Head);
+    let etag = current.and_then(|validators| validators.etag.as_ref());
+
+    if let Some(condition) = TagCondition::from_headers(headers, "If-Match") {
+        if !condition.matches(etag, true) {
+            return Precondition::Failed;
+        }
+    } else if let Some(since) = header_date_secs(headers, "If-Unmodified-Since") {
+        let modified = current.and_then(Validators::last_modified_secs);
+        if modified.is_none_or(|modified| modified > since) {
+            return Precondition::Failed;
+        }
+    }
+
+    if let Some(condition) = TagCondition::from_headers(headers, "If-None-Match") {
+        if condition.matches(etag, false) {
+            return if is_read {
+                Precondition::NotModified
+            } else {
+                Precondition::Failed
+            };
+        }
+    } else if is_read {
+        let modified = current.and_then(Validators::last_modified_secs);
+        if let (Some(since), Some(modified)) =
+            (header_date_secs(headers, "If-Modified-Since"), modified)
+        {
+            if modified <= since {
+                return Precondition::NotModified;
+            }
+        }
+    }
+
+    Precondition::Proceed
+}
+
+// Response for a precondition that stopped the request, None when it may go ahead
+pub fn response_for(
+    request: &RequestHeaders,
+    current: Option<&Validators>,
+) -> Option<HTTPResponse> {
+    let status = match evaluate(request, current) {
+        Precondition::Proceed => return None,
+        Precondition::NotModified => HTTPStatus::NotModified,
+        Precondition::Failed => HTTPStatus::PreconditionFailed,
+    };
+    let mut response = HTTPResponse::new(status);
+    if let Some(validators) = current {
+        validators.add_to(&mut response.headers);
+    }
+    Some(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::request::parse_request_headers;
+    use std::time::Duration;
+
+    const MODIFIED: u64 = 784_111_777; // Sun, 06 Nov 1994 08:49:37 GMT
+
+    fn validators() -> Validators {
+        Validators {
+            etag: Some(EntityTag::strong("v1")),
+            last_modified: Some(UNIX_EPOCH + Duration::from_millis(MODIFIED * 1000 + 250)),
+        }
+    }
+
+    async fn check(method: &str, headers: &str, current: Option<&Validators>) -> Precondition {
+        let raw = format!("{} /files/a HTTP/1.1\r\n{}\r\n", method, headers);
+        let request = parse_request_headers(&raw).await.unwrap();
+        evaluate(&request, current)
+    }
+
+    #[test]
+    fn test_entity_tags() {
+        assert_eq!(EntityTag::parse("\"abc\""), Some(EntityTag::strong("abc")));
+        assert_eq!(EntityTag::parse("W/\"abc\""), Some(EntityTag::weak("abc")));
+        assert_eq!(EntityTag::parse("\"\""), Some(EntityTag::strong("")));
+        assert_eq!(EntityTag::parse("abc"), None);
+        assert_eq!(EntityTag::parse("\"a\"b\""), None);
+        assert_eq!(EntityTag::weak("abc").to_string(), "W/\"abc\"");
+
+        let (strong, weak) = (EntityTag::strong("1"), EntityTag::weak("1"));
+        assert!(strong.strong_eq(&strong));
+        assert!(!strong.strong_eq(&weak));
+        assert!(strong.weak_eq(&weak));
+        assert!(!strong.weak_eq(&EntityTag::strong("2")));
+    }
+
+    #[tokio::test]
+    async fn test_if_none_match() {
+        let current = validators();
+        let cases = [
+            ("If-None-Match: \"v1\"\r\n", Precondition::NotModified),
+            ("If-None-Match: W/\"v1\"\r\n", Precondition::NotModified),
+            (
+                "If-None-Match: \"v0\", \"v1\"\r\n",
+                Precondition::NotModified,
+            ),
+            ("If-None-Match: *\r\n", Precondition::NotModified),
+            ("If-None-Match: \"v2\"\r\n", Precondition::Proceed),
+        ];
+        for (headers, expected) in cases {
+            assert_eq!(
+                check("GET", headers, Some(&current)).await,
+                expected,
+                "{}",
+                headers
+            );
+        }
+
+        // on writes a matching tag fails the request, `*` only allows creating new resources
+        assert_eq!(
+            check("POST", "If-None-Match: *\r\n", Some(&current)).await,
+            Precondition::Failed
+        );
+        assert_eq!(
+            check("POST", "If-None-Match: *\r\n", None).await,
+            Precondition::Proceed
+        );
+    }
+
+    #[tokio::test]
+    async fn test_if_modified_since() {
+        let current = validators();
+        let cases = [
+            ("Sun, 06 Nov 1994 08:49:37 GMT", Precondition::NotModified),
+            ("Mon, 07 Nov 1994 08:49:37 GMT", Precondition::NotModified),
+            ("Sun, 06 Nov 1994 08:49:36 GMT", Precondition::Proceed),
+            ("not a date", Precondition::Proceed),
+        ];
+        for (date, expected) in cases {
+            let headers = format!("If-Modified-Since: {}\r\n", date);
+            assert_eq!(
+                check("GET", &headers, Some(&current)).await,
+                expected,
+                "{}",
+                date
+            );
+        }
+
+        // ignored when If-None-Match is present and on writes
+        let headers =
+            "If-None-Match: \"v2\"\r\nIf-Modified-Since: Mon, 07 Nov 1994 08:49:37 GMT\r\n";
+        assert_eq!(
+            check("GET", headers, Some(&current)).await,
+            Precondition::Proceed
+        );
+        let headers = "If-Modified-Since: Mon, 07 Nov 1994 08:49:37 GMT\r\n";
+        assert_eq!(
+            check("POST", headers, Some(&current)).await,
+            Precondition::Proceed
+        );
+    }
+
+    #[tokio::test]
+    async fn test_if_match() {
+        let current = validators();
+        let cases = [
+            ("If-Match: \"v1\"\r\n", Precondition::Proceed),
+            ("If-Match: *\r\n", Precondition::Proceed),
+            ("If-Match: \"v2\"\r\n", Precondition::Failed),
+            // weak tags never match strongly
+            ("If-Match: W/\"v1\"\r\n", Precondition::Failed),
+        ];
+        for (headers, expected) in cases {
+            assert_eq!(
+                check("POST", headers, Some(&current)).await,
+                expected,
+                "{}",
+                headers
+            );
+        }
+        assert_eq!(
+            check("POST", "If-Match: *\r\n", None).await,
+            Precondition::Failed
+        );
+    }
+
+    #[tokio::test]
+    async fn test_if_unmodified_since() {
+        let current = validators();
+        let headers = "If-Unmodified-Since: Sun, 06 Nov 1994 08:49:37 GMT\r\n";
+        assert_eq!(
+            check("POST", headers, Some(&current)).await,
+            Precondition::Proceed
+        );
+        let headers = "If-Unmodified-Since: Sun, 06 Nov 1994 08:49:36 GMT\r\n";
+        assert_eq!(
+            check("POST", headers, Some(&current)).await,
+            Precondition::Failed
+        );
+
+        // If-Match takes precedence
+        let headers = "If-Match: \"v1\"\r\nIf-Unmodified-Since: Sun, 06 Nov 1994 08:49:36 GMT\r\n";
+        assert_eq!(
+            check("POST", headers, Some(&current)).await,
+            Precondition::Proceed
+        );
+    }
+
+    #[tokio::test]
+    async fn test_not_modified_response_carries_validators() {
+        let current = validators();
+        let raw = "GET /files/a HTTP/1.1\r\nIf-None-Match: \"v1\"\r\n\r\n";
+        let request = parse_request_headers(raw).await.unwrap();
+
+        let response = response_for(&request, Some(&current)).unwrap();
+        assert_eq!(
+            response.to_string(),
+            "HTTP/1.1 304 Not Modified\r\nETag: \"v1\"\r\nLast-Modified: Sun, 06 Nov 1994 08:49:37 GMT\r\n\r\n"
+        );
+    }
+}
@@ -1,17 +1,86 @@
+use crate::parser::Limits;
+use crate::request::Timeouts;
+
 use std::env;
+use std::path::PathBuf;
+use std::time::Duration;
+
+const DEFAULT_HOSTNAME: &str = "127.0.0.1";
+const DEFAULT_PORT: &str = "4221";
+const DEFAULT_BUFFER_SIZE: usize = 1024;
+const DEFAULT_KEEP_ALIVE_TIMEOUT_SECS: u64 = 5;
+const DEFAULT_MAX_REQUESTS_PER_CONNECTION: usize = 100;
+const DEFAULT_WRITE_TIMEOUT_SECS: u64 = 30;
+const DEFAULT_DRAIN_TIMEOUT_SECS: u64 = 30;
 
 pub struct Settings {
     pub hostname: String,
     pub port: String,
     pub buffer_size: usize,
+    // how long an idle persistent connection waits for its next request
+    pub keep_alive_timeout: Duration,
+    // requests served on one connection before it is closed
+    pub max_requests_per_connection: usize,
+    // longer request lines are answered with 414
+    pub max_request_line: usize,
+    // larger header sections or more header fields are answered with 431
+    pub max_header_bytes: usize,
+    pub max_headers: usize,
+    // larger bodies are answered with 413
+    pub max_body_size: usize,
+    // how long a new connection waits for the first byte of its first request
+    pub first_byte_timeout: Duration,
+    // time from the first byte of a request to the end of its headers, then 408
+    pub header_timeout: Duration,
+    // time for a request body before more is earned at `min_body_rate` bytes per second
+    pub body_timeout: Duration,
+    pub min_body_rate: usize,
+    // how long writing a response may stall before the connection is dropped
+    pub write_timeout: Duration,
+    // how long a shutdown waits for open connections before closing them forcibly
+    pub drain_timeout: Duration,
+    // file the connection log is appended to instead of stdout, reopened on SIGUSR1
+    pub log_file: Option<PathBuf>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        let limits = Limits::default();
+        let timeouts = Timeouts::default();
+        Settings {
+            hostname: DEFAULT_HOSTNAME.to_string(),
+            port: DEFAULT_PORT.to_string(),
+            buffer_size: DEFAULT_BUFFER_SIZE,
+            keep_alive_timeout: Duration::from_secs(DEFAULT_KEEP_ALIVE_TIMEOUT_SECS),
+            max_requests_per_connection: DEFAULT_MAX_REQUESTS_PER_CONNECTION,
+            max_request_line: limits.max_request_line,
+            max_header_bytes: limits.max_header_bytes,
+            max_headers: limits.max_headers,
+            max_body_size: limits.max_body_size,
+            first_byte_timeout: timeouts.idle,
+            header_timeout: timeouts.headers,
+            body_timeout: timeouts.body,
+            min_body_rate: timeouts.min_body_rate,
+            write_timeout: Duration::from_secs(DEFAULT_WRITE_TIMEOUT_SECS),
+            drain_timeout: Duration::from_secs(DEFAULT_DRAIN_TIMEOUT_SECS),
+            log_file: None,
+        }
+    }
+}
+
+fn env_secs(name: &str, default: Duration) -> Duration {
+    Duration::from_secs(env_or(name, default.as_secs()))
+}
+
+fn env_or<T: std::str::FromStr>(name: &str, default: T) -> T {
+    env::var(name)
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(default)
 }
 
 impl Settings {
     pub async fn load() -> Result<Self, &'static str> {
-        const DEFAULT_HOSTNAME: &str = "127.0.0.1";
-        const DEFAULT_PORT: &str = "4221";
-        const DEFAULT_BUFFER_SIZE: usize = 1024;
-
         let hostname = env::var("HOSTNAME").unwrap_or_else(|_| DEFAULT_HOSTNAME.to_string());
         let port = env::var("PORT").unwrap_or_else(|_| DEFAULT_PORT.to_string());
         let buffer_size_str =
@@ -20,12 +89,63 @@ impl Settings {
             .parse::<usize>()
             .unwrap_or(DEFAULT_BUFFER_SIZE);
 
+        let keep_alive_timeout = Duration::from_secs(env_or(
+            "KEEP_ALIVE_TIMEOUT",
+            DEFAULT_KEEP_ALIVE_TIMEOUT_SECS,
+        ));
+        let max_requests_per_connection = env_or(
+            "MAX_REQUESTS_PER_CONNECTION",
+            DEFAULT_MAX_REQUESTS_PER_CONNECTION,
+        );
+
+        let limits = Limits::default();
+        let timeouts = Timeouts::default();
         Ok(Settings {
             hostname,
             port,
             buffer_size,
+            keep_alive_timeout,
+            max_requests_per_connection,
+            max_request_line: env_or("MAX_REQUEST_LINE", limits.max_request_line),
+            max_header_bytes: env_or("MAX_HEADER_BYTES", limits.max_header_bytes),
+            max_headers: env_or("MAX_HEADERS", limits.max_headers),
+            max_body_size: env_or("MAX_BODY_SIZE", limits.max_body_size),
+            first_byte_timeout: env_secs("FIRST_BYTE_TIMEOUT", timeouts.idle),
+            header_timeout: env_secs("HEADER_TIMEOUT", timeouts.headers),
+            body_timeout: env_secs("BODY_TIMEOUT", timeouts.body),
+            min_body_rate: env_or("MIN_BODY_RATE", timeouts.min_body_rate),
+            write_timeout: env_secs(
+                "WRITE_TIMEOUT",
+                Duration::from_secs(DEFAULT_WRITE_TIMEOUT_SECS),
+            ),
+            drain_timeout: env_secs(
+                "DRAIN_TIMEOUT",
+                Duration::from_secs(DEFAULT_DRAIN_TIMEOUT_SECS),
+            ),
+            log_file: env::var_os("LOG_FILE").map(PathBuf::from),
         })
     }
+
+    // Size limits every request read by the server is held to
+    pub fn limits(&self) -> Limits {
+        Limits {
+            max_request_line: self.max_request_line,
+            max_header_bytes: self.max_header_bytes,
+            max_headers: self.max_headers,
+            max_body_size: self.max_body_size,
+        }
+    }
+
+    // Timeouts for reading the first request of a connection, later requests wait for
+    // `keep_alive_timeout` instead of `first_byte_timeout`
+    pub fn timeouts(&self) -> Timeouts {
+        Timeouts {
+            idle: self.first_byte_timeout,
+            headers: self.header_timeout,
+            body: self.body_timeout,
+            min_body_rate: self.min_body_rate,
+        }
+    }
 }
 
 #[cfg(test)]
@@ -40,6 +160,13 @@ mod tests {
         assert_eq!(settings.buffer_size, 1024);
         assert_eq!(settings.hostname, "127.0.0.1");
         assert_eq!(settings.port, "4221");
+        assert_eq!(settings.keep_alive_timeout, Duration::from_secs(5));
+        assert_eq!(settings.max_requests_per_connection, 100);
+        assert_eq!(settings.limits(), Limits::default());
+        assert_eq!(settings.timeouts(), Timeouts::default());
+        assert_eq!(settings.write_timeout, Duration::from_secs(30));
+        assert_eq!(settings.drain_timeout, Duration::from_secs(30));
+        assert_eq!(settings.log_file, None);
     }
 
     #[tokio::test]
@@ -55,7 +182,7 @@ mod tests {
             settings.buffer_size,
             env::var("BUFFER_SIZE")
                 .unwrap()
-                .parse()
+                .parse::<usize>()
                 .expect("BUFFER_SIZE must be a number")
         );
         assert_eq!(settings.hostname, env::var("HOSTNAME").unwrap());
@@ -1,103 +1,443 @@
-use crate::cli;
 use crate::config::Settings;
-use crate::file;
-use crate::http::{HTTPBody, HTTPContentType, HTTPStatus};
-use crate::request;
+use crate::http::{HTTPStatus, Method, Version};
+use crate::log;
+use crate::parser;
+use crate::request::{self, RequestError};
 use crate::response::HTTPResponse;
+use crate::router::Router;
+use crate::shutdown::Draining;
 
-use std::io::{self};
-use std::path::Path;
+use bytes::BytesMut;
+use std::future::Future;
+use std::io::{self, ErrorKind};
+use std::pin::Pin;
 use std::sync::Arc;
-use tokio::io::AsyncWriteExt;
-
-pub async fn handle_connection(
-    mut stream: tokio::net::TcpStream,
-    _config: Arc<Settings>,
-) -> io::Result<()> {
-    println!("Accepted new connection!");
-
-    match request::parse_stream(&mut stream).await {
-        Ok(request) => {
-            let response = match request.headers.path.as_str() {
-                "/" => HTTPResponse {
-                    status: HTTPStatus::Ok,
-                    body: None,
-                },
-                "/user-agent" => HTTPResponse {
-                    status: HTTPStatus::Ok,
-                    body: Some(HTTPBody {
-                        body: request.headers.user_agent,
-                        content_type: HTTPContentType::PlainText,
-                    }),
-                },
-                path if path.starts_with("/echo/") => {
-                    let to_echo = &path[6..];
-                    let to_echo = to_echo.to_string();
-                    HTTPResponse {
-                        status: HTTPStatus::Ok,
-                        body: Some(HTTPBody {
-                            body: to_echo,
-                            content_type: HTTPContentType::PlainText,
-                        }),
-                    }
-                }
-                path if path.starts_with("/files/") => {
-                    let directory =
-                        cli::get_cli_arg_by_name("--directory").expect("Argument not found");
-
-                    let safe_filename = file::parse_filename_from_request_path(&path)
-                        .expect("Invalid filename in request");
-
-                    let full_path = Path::new(&directory).join(safe_filename);
-                    println!("Full path to file: {}", full_path.display());
-                    if request.headers.method == "GET" {
-                        let result = match file::read_file_to_string(&full_path) {
-                            Some(file_content) => HTTPResponse {
-                                status: HTTPStatus::Ok,
-                                body: Some(HTTPBody {
-                                    body: file_content,
-                                    content_type: HTTPContentType::File,
-                                }),
-                            },
-                            None => HTTPResponse {
-                                status: HTTPStatus::NotFound,
-                                body: None,
-                            },
-                        };
-                        result
-                    } else if request.headers.method == "POST" {
-                        let body = request.body.unwrap();
-                        file::write_string_to_file(&full_path, &body)?;
-
-                        HTTPResponse {
-                            status: HTTPStatus::Created,
-                            body: Some(HTTPBody {
-                                body: body,
-                                content_type: HTTPContentType::File,
-                            }),
-                        }
-                    } else {
-                        HTTPResponse {
-                            status: HTTPStatus::BadRequest,
-                            body: None,
-                        }
-                    }
-                }
-                _ => HTTPResponse {
-                    status: HTTPStatus::NotFound,
-                    body: None,
-                },
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
+use tokio::time::{self, Instant, Sleep};
+
+pub async fn handle_connection<S>(
+    mut stream: S,
+    config: Arc<Settings>,
+    router: Arc<Router>,
+    mut draining: Draining,
+) -> io::Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    log::write("Accepted new connection!");
+
+    // bytes read from the socket that belong to requests not handled yet
+    let mut buffer = BytesMut::new();
+    let mut requests_served = 0;
+    let limits = config.limits();
+    let timeouts = config.timeouts();
+
+    loop {
+        if buffer.is_empty() {
+            // a persistent connection is closed once it has been idle for too long
+            let idle = if requests_served == 0 {
+                config.first_byte_timeout
+            } else {
+                config.keep_alive_timeout
             };
-            println!("{}", format!("{}", response));
-            stream.write_all(response.to_string().as_bytes()).await?;
+            let started = tokio::select! {
+                started = request::wait_for_request(&mut stream, &mut buffer, idle) => started,
+                // nothing is in progress, so a draining server can close right away
+                _ = draining.wait() => break,
+            };
+            // the client left or stayed silent, there is nobody to answer
+            if !matches!(started, Ok(true)) {
+                break;
+            }
         }
-        Err(_) => {
-            let response = HTTPResponse {
-                status: HTTPStatus::InternalServerError,
-                body: None,
+
+        // known once the request line is, so even a rejected request is answered in its version
+        let (version, next_request) =
+            match request::read_head(&mut stream, &mut buffer, &limits, &timeouts).await {
+                Ok(Some(headers)) => (
+                    Some(headers.version),
+                    request::read_body(&mut stream, &mut buffer, headers, &limits, &timeouts)
+                        .await
+                        .map(Some),
+                ),
+                Ok(None) => (None, Ok(None)),
+                Err(e) => (parser::request_version(&buffer), Err(e)),
             };
-            stream.write_all(response.to_string().as_bytes()).await?;
+
+        match next_request {
+            Ok(Some(request)) => {
+                requests_served += 1;
+                let keep_alive = request.headers.keep_alive()
+                    && requests_served < config.max_requests_per_connection;
+                let version = request.headers.version;
+                let head_only = request.headers.method == Method::Head;
+
+                let mut response = router.handle(request).await;
+                response.version = version;
+                // without a body there is nothing to delimit by closing the connection, and a
+                // draining server lets the client know this was the last response
+                let keep_alive = keep_alive
+                    && (head_only || !response.is_close_delimited())
+                    && !draining.is_set();
+                if !keep_alive {
+                    response.headers.insert("Connection", "close");
+                } else if version == Version::Http10 {
+                    response.headers.insert("Connection", "keep-alive");
+                }
+                log::write(&response.head());
+                let mut writer = StallTimeout::new(&mut stream, config.write_timeout);
+                if head_only {
+                    response.write_head_to(&mut writer).await?;
+                } else {
+                    response.write_to(&mut writer).await?;
+                }
+
+                if !keep_alive {
+                    break;
+                }
+            }
+            // client closed the connection between requests
+            Ok(None) | Err(RequestError::IdleTimeout) => break,
+            // the client went away mid-request, there is nobody left to answer
+            Err(RequestError::Io(e)) => {
+                log::write(&format!("Connection lost while reading request: {}", e));
+                break;
+            }
+            Err(e) => {
+                let status = match e {
+                    // the rest of the stream cannot be framed reliably after a parse error
+                    RequestError::Parse(e) => {
+                        log::error(&format!("Rejecting malformed request: {}", e));
+                        e.status()
+                    }
+                    RequestError::Timeout => {
+                        log::error("Dropping request that was not received in time");
+                        HTTPStatus::RequestTimeout
+                    }
+                    _ => HTTPStatus::InternalServerError,
+                };
+                let mut response = HTTPResponse::builder()
+                    .status(status)
+                    .header("Connection", "close")
+                    .build();
+                if let Some(version) = version {
+                    response.version = version;
+                }
+                let mut writer = StallTimeout::new(&mut stream, config.write_timeout);
+                writer.write_all(&response.to_bytes()).await?;
+                break;
+            }
         }
     }
+    // the peer may already be gone, there is nothing left to report in that case
+    let _ = stream.shutdown().await;
     Ok(())
 }
+
+// Fails writes that make no progress for `timeout`, so a client that stops reading cannot hold
+// the connection open. Slow clients are fine as long as they keep accepting data.
+struct StallTimeout<'a, W> {
+    inner: &'a mut W,
+    timeout: Duration,
+    sleep: Pin<Box<Sleep>>,
+}
+
+impl<'a, W> StallTimeout<'a, W> {
+    fn new(inner: &'a mut W, timeout: Duration) -> Self {
+        StallTimeout {
+            inner,
+            timeout,
+            sleep: Box::pin(time::sleep(timeout)),
+        }
+    }
+
+    fn poll_progress<T>(
+        &mut self,
+        cx: &mut Context<'_>,
+        poll: Poll<io::Result<T>>,
+    ) -> Poll<io::Result<T>> {
+        match poll {
+            Poll::Ready(result) => {
+                let deadline = Instant::now() + self.timeout;
+                self.sleep.as_mut().reset(deadline);
+                Poll::Ready(result)
+            }
+            Poll::Pending => match self.sleep.as_mut().poll(cx) {
+                Poll::Ready(()) => Poll::Ready(Err(io::Error::new(
+                    ErrorKind::TimedOut,
+                    "client stopped reading the response",
+                ))),
+                Poll::Pending => Poll::Pending,
+            },
+        }
+    }
+}
+
+impl<W> AsyncWrite for StallTimeout<'_, W>
+where
+    W: AsyncWrite + Unpin,
+{
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let poll = Pin::new(&mut *self.inner).poll_write(cx, buf);
+        self.poll_progress(cx, poll)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let poll = Pin::new(&mut *self.inner).poll_flush(cx);
+        self.poll_progress(cx, poll)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let poll = Pin::new(&mut *self.inner).poll_shutdown(cx);
+        self.poll_progress(cx, poll)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::HTTPBody;
+    use crate::request::ParsedRequest;
+    use tokio::io::AsyncReadExt;
+
+    fn router() -> Router {
+        Router::new()
+            .get("/", |_: ParsedRequest| async {
+                HTTPResponse::new(HTTPStatus::Ok)
+            })
+            .get("/echo/*text", |request: ParsedRequest| async move {
+                let text = request.params.get("text").unwrap_or("").to_string();
+                HTTPResponse::builder().body(HTTPBody::text(text)).build()
+            })
+    }
+
+    async fn exchange(settings: Settings, raw: &str) -> String {
+        let (mut client, server) = tokio::io::duplex(64 * 1024);
+        let handle = tokio::spawn(handle_connection(
+            server,
+            Arc::new(settings),
+            Arc::new(router()),
+            Draining::never(),
+        ));
+
+        client.write_all(raw.as_bytes()).await.unwrap();
+        let mut output = String::new();
+        client.read_to_string(&mut output).await.unwrap();
+        handle.await.unwrap().unwrap();
+        output
+    }
+
+    #[tokio::test]
+    async fn test_pipelined_requests_share_connection() {
+        let raw =
+            "GET /echo/one HTTP/1.1\r\n\r\nGET /echo/two HTTP/1.1\r\nConnection: close\r\n\r\n";
+        let output = exchange(Settings::default(), raw).await;
+
+        assert_eq!(
+            output,
+            "HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\nContent-Length: 3\r\n\r\none\
+             HTTP/1.1 200 OK\r\nConnection: close\r\nContent-Type: text/plain\r\nContent-Length: 3\r\n\r\ntwo"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_head_request_mirrors_get_without_body() {
+        let raw =
+            "HEAD /echo/hello HTTP/1.1\r\n\r\nGET /echo/hi HTTP/1.1\r\nConnection: close\r\n\r\n";
+        let output = exchange(Settings::default(), raw).await;
+
+        assert_eq!(
+            output,
+            "HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\nContent-Length: 5\r\n\r\n\
+             HTTP/1.1 200 OK\r\nConnection: close\r\nContent-Type: text/plain\r\nContent-Length: 2\r\n\r\nhi"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_http10_closes_without_keep_alive() {
+        let raw = "GET / HTTP/1.0\r\n\r\nGET / HTTP/1.0\r\n\r\n";
+        let output = exchange(Settings::default(), raw).await;
+        assert_eq!(output.matches("HTTP/1.0 200 OK").count(), 1);
+        assert!(output.contains("Connection: close"));
+    }
+
+    #[tokio::test]
+    async fn test_http10_keep_alive_opt_in() {
+        let raw = "GET / HTTP/1.0\r\nConnection: keep-alive\r\n\r\nGET / HTTP/1.0\r\n\r\n";
+        let output = exchange(Settings::default(), raw).await;
+        assert_eq!(output.matches("HTTP/1.0 200 OK").count(), 2);
+        assert!(output.contains("Connection: keep-alive"));
+    }
+
+    #[tokio::test]
+    async fn test_max_requests_per_connection() {
+        let settings = Settings {
+            max_requests_per_connection: 2,
+            ..Settings::default()
+        };
+        let raw = "GET / HTTP/1.1\r\n\r\n".repeat(3);
+        let output = exchange(settings, &raw).await;
+        assert_eq!(output.matches("HTTP/1.1 200 OK").count(), 2);
+        assert!(output.ends_with("Connection: close\r\nContent-Length: 0\r\n\r\n"));
+    }
+
+    #[tokio::test]
+    async fn test_size_limits() {
+        let limited = || Settings {
+            max_request_line: 32,
+            max_header_bytes: 64,
+            max_headers: 2,
+            max_body_size: 4,
+            ..Settings::default()
+        };
+        let cases = [
+            (
+                format!("GET /echo/{} HTTP/1.1\r\n\r\n", "a".repeat(32)),
+                "HTTP/1.1 414 URI Too Long\r\n",
+            ),
+            (
+                format!("GET / HTTP/1.1\r\nX-Big: {}\r\n\r\n", "a".repeat(64)),
+                "HTTP/1.1 431 Request Header Fields Too Large\r\n",
+            ),
+            (
+                "GET / HTTP/1.1\r\nA: 1\r\nB: 2\r\nC: 3\r\n\r\n".to_string(),
+                "HTTP/1.1 431 Request Header Fields Too Large\r\n",
+            ),
+            (
+                "POST / HTTP/1.1\r\nContent-Length: 5\r\n\r\nhello".to_string(),
+                "HTTP/1.1 413 Content Too Large\r\n",
+            ),
+            // a request line that was understood sets the version of the rejection
+            (
+                format!("GET / HTTP/1.0\r\nX-Big: {}\r\n\r\n", "a".repeat(64)),
+                "HTTP/1.0 431 Request Header Fields Too Large\r\n",
+            ),
+            (
+                "POST / HTTP/1.0\r\nContent-Length: 5\r\n\r\nhello".to_string(),
+                "HTTP/1.0 413 Content Too Large\r\n",
+            ),
+            (
+                "GET / HTTP/1.0\r\nBad Header\r\n\r\n".to_string(),
+                "HTTP/1.0 400 Bad Request\r\n",
+            ),
+            // empty lines before the request line do not escape the limits
+            (
+                "\r\n".repeat(1000) + "GET / HTTP/1.1\r\n\r\n",
+                "HTTP/1.1 400 Bad Request\r\n",
+            ),
+        ];
+        for (raw, status_line) in cases {
+            let output = exchange(limited(), &raw).await;
+            assert!(output.starts_with(status_line), "{}", output);
+            assert!(output.contains("Connection: close\r\n"));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_truncated_request_gets_no_response() {
+        for raw in [
+            "GET / HTTP/1.1\r\nHost",
+            "POST / HTTP/1.1\r\nContent-Length: 5\r\n\r\nhe",
+        ] {
+            let (mut client, server) = tokio::io::duplex(1024);
+            let handle = tokio::spawn(handle_connection(
+                server,
+                Arc::new(Settings::default()),
+                Arc::new(router()),
+                Draining::never(),
+            ));
+
+            client.write_all(raw.as_bytes()).await.unwrap();
+            client.shutdown().await.unwrap();
+            let mut output = String::new();
+            client.read_to_string(&mut output).await.unwrap();
+            handle.await.unwrap().unwrap();
+            assert_eq!(output, "", "{}", raw);
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_idle_keep_alive_connection_times_out() {
+        let (mut client, server) = tokio::io::duplex(1024);
+        let handle = tokio::spawn(handle_connection(
+            server,
+            Arc::new(Settings::default()),
+            Arc::new(router()),
+            Draining::never(),
+        ));
+
+        client.write_all(b"GET / HTTP/1.1\r\n\r\n").await.unwrap();
+        // the paused clock auto-advances past the idle timeout while nothing else is sent
+        let mut output = String::new();
+        client.read_to_string(&mut output).await.unwrap();
+        handle.await.unwrap().unwrap();
+        assert_eq!(output, "HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_incomplete_headers_time_out_with_408() {
+        let (mut client, server) = tokio::io::duplex(1024);
+        let handle = tokio::spawn(handle_connection(
+            server,
+            Arc::new(Settings::default()),
+            Arc::new(router()),
+            Draining::never(),
+        ));
+
+        client
+            .write_all(b"GET / HTTP/1.1\r\nHost: a")
+            .await
+            .unwrap();
+        let mut output = String::new();
+        client.read_to_string(&mut output).await.unwrap();
+        handle.await.unwrap().unwrap();
+        assert!(output.starts_with("HTTP/1.1 408 Request Timeout\r\n"));
+        assert!(output.contains("Connection: close\r\n"));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_silent_connection_is_closed() {
+        let (mut client, server) = tokio::io::duplex(1024);
+        let settings = Settings {
+            first_byte_timeout: Duration::from_secs(3),
+            ..Settings::default()
+        };
+        let handle = tokio::spawn(handle_connection(
+            server,
+            Arc::new(settings),
+            Arc::new(router()),
+            Draining::never(),
+        ));
+
+        let started = Instant::now();
+        let mut output = String::new();
+        client.read_to_string(&mut output).await.unwrap();
+        handle.await.unwrap().unwrap();
+        assert_eq!(output, "");
+        assert_eq!(started.elapsed(), Duration::from_secs(3));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_stalled_response_write_times_out() {
+        let (mut client, server) = tokio::io::duplex(256);
+        let handle = tokio::spawn(handle_connection(
+            server,
+            Arc::new(Settings::default()),
+            Arc::new(router()),
+            Draining::never(),
+        ));
+
+        // the response does not fit the pipe and the client never reads it
+        let request = format!("GET /echo/{} HTTP/1.1\r\n\r\n", "a".repeat(4096));
+        client.write_all(request.as_bytes()).await.unwrap();
+        let error = handle.await.unwrap().unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::TimedOut);
+        drop(client);
+    }
+}
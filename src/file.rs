@@ -1,32 +1,516 @@
-use std::fs;
-use std::fs::File;
-use std::io::{self, Write};
-use std::path::Path;
-
-pub fn parse_filename_from_request_path(path: &str) -> Option<String> {
-    let filename = path[7..].to_string(); // Assuming '/files/' is always present
-    let path = Path::new(&filename);
-
-    // Check for directory traversal attempts
-    if path
-        .components()
-        .any(|comp| comp == std::path::Component::ParentDir)
-    {
-        return None; // Reject paths with '..'
+use crate::conditional::{EntityTag, Validators};
+use crate::http::HTTPStatus;
+
+use std::collections::HashMap;
+use std::fs::Metadata;
+use std::io::{self, ErrorKind, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, LazyLock, Mutex, Weak};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::fs::OpenOptions;
+use tokio::io::{AsyncRead, AsyncSeekExt, AsyncWriteExt};
+use tokio::sync::{Mutex as AsyncMutex, OwnedMutexGuard};
+
+// Makes temporary file names unique between concurrent writes of one process
+static TEMP_FILE_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+// Generation of each file this process committed, by device and inode. Filesystems reuse inode
+// numbers, so a rewritten file can get the inode, size and timestamp of an earlier version, the
+// generation still tells the two apart in the entity tag.
+static GENERATIONS: LazyLock<Mutex<HashMap<(u64, u64), u64>>> = LazyLock::new(Default::default);
+
+// Starts at the time the process started, so generations of an earlier run are not repeated
+static NEXT_GENERATION: LazyLock<AtomicU64> = LazyLock::new(|| {
+    let started = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    AtomicU64::new(started.as_nanos() as u64)
+});
+
+pub async fn open_file(file_path: &Path) -> Option<tokio::fs::File> {
+    tokio::fs::File::open(file_path).await.ok()
+}
+
+// Replace the content of `file_path` with everything `source` produces, creating the file if
+// needed. The data goes to a temporary file next to the target which is synced and renamed over
+// it, so readers and crashes only ever see the old or the complete new content. Missing parent
+// directories are created with `create_parents`, otherwise they fail the write with NotFound.
+// A replaced file keeps its permissions. Returns the number of bytes written.
+pub async fn write_file_atomic<R>(
+    file_path: &Path,
+    source: &mut R,
+    create_parents: bool,
+) -> io::Result<u64>
+where
+    R: AsyncRead + Unpin + ?Sized,
+{
+    let parent = parent_directory(file_path);
+    if create_parents {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+
+    let temp_path = temp_path_for(file_path)?;
+    let written = async {
+        let mut file = create_temp_file(&temp_path).await?;
+        let written = tokio::io::copy(source, &mut file).await?;
+        // like `update_file_atomic`, which copies them along with the content
+        if let Ok(existing) = tokio::fs::metadata(file_path).await {
+            file.set_permissions(existing.permissions()).await?;
+        }
+        file.sync_all().await?;
+        Ok(written)
+    }
+    .await;
+    match written {
+        Ok(written) => commit(&temp_path, file_path).await.map(|_| written),
+        Err(e) => Err(discard(&temp_path, e).await),
+    }
+}
+
+// Change an existing file atomically: `to_write` replaces the bytes from `offset` on, or is
+// appended when there is no offset. The file is copied, changed and renamed over the original
+// like in `write_file_atomic`.
+pub async fn update_file_atomic(
+    file_path: &Path,
+    offset: Option<u64>,
+    to_write: &[u8],
+) -> io::Result<()> {
+    let temp_path = temp_path_for(file_path)?;
+    let updated = async {
+        // create the temporary file exclusively before filling it with the original content
+        drop(create_temp_file(&temp_path).await?);
+        tokio::fs::copy(file_path, &temp_path).await?;
+
+        let mut file = OpenOptions::new().write(true).open(&temp_path).await?;
+        match offset {
+            Some(offset) => file.seek(SeekFrom::Start(offset)).await?,
+            None => file.seek(SeekFrom::End(0)).await?,
+        };
+        file.write_all(to_write).await?;
+        file.sync_all().await
+    }
+    .await;
+    match updated {
+        Ok(()) => commit(&temp_path, file_path).await,
+        Err(e) => Err(discard(&temp_path, e).await),
+    }
+}
+
+// Lets one writer at a time change a path, so updates that read the file first, like
+// `update_file_atomic` or checking preconditions, cannot overwrite each other's changes.
+// Locks of paths nobody holds are dropped.
+#[derive(Default)]
+pub struct PathLocks {
+    locks: Mutex<HashMap<PathBuf, Weak<AsyncMutex<()>>>>,
+}
+
+impl PathLocks {
+    pub async fn lock(&self, file_path: &Path) -> OwnedMutexGuard<()> {
+        let lock = {
+            let mut locks = self.locks.lock().unwrap_or_else(|e| e.into_inner());
+            locks.retain(|_, lock| lock.strong_count() > 0);
+            match locks.get(file_path).and_then(Weak::upgrade) {
+                Some(lock) => lock,
+                None => {
+                    let lock = Arc::new(AsyncMutex::new(()));
+                    locks.insert(file_path.to_path_buf(), Arc::downgrade(&lock));
+                    lock
+                }
+            }
+        };
+        lock.lock_owned().await
+    }
+}
+
+fn parent_directory(file_path: &Path) -> &Path {
+    match file_path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    }
+}
+
+// Hidden, unique sibling of the target, on the same filesystem so the rename is atomic
+fn temp_path_for(file_path: &Path) -> io::Result<PathBuf> {
+    let file_name = file_path
+        .file_name()
+        .ok_or_else(|| io::Error::new(ErrorKind::InvalidInput, "path has no file name"))?;
+    let unique = TEMP_FILE_COUNTER.fetch_add(1, Ordering::Relaxed);
+    let temp_name = format!(
+        ".{}.{}-{}.tmp",
+        file_name.to_string_lossy(),
+        std::process::id(),
+        unique
+    );
+    Ok(parent_directory(file_path).join(temp_name))
+}
+
+async fn create_temp_file(temp_path: &Path) -> io::Result<tokio::fs::File> {
+    OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(temp_path)
+        .await
+}
+
+// Move the synced temporary file into place and make the rename itself durable. The new file
+// gets its generation first, so its content is never seen with the generation of an older file.
+async fn commit(temp_path: &Path, file_path: &Path) -> io::Result<()> {
+    let renamed = async {
+        let metadata = tokio::fs::metadata(temp_path).await?;
+        forget_generation(file_path).await;
+        assign_generation(&metadata);
+        tokio::fs::rename(temp_path, file_path).await
+    }
+    .await;
+    if let Err(e) = renamed {
+        return Err(discard(temp_path, e).await);
     }
+    sync_directory(parent_directory(file_path)).await
+}
+
+fn generations() -> std::sync::MutexGuard<'static, HashMap<(u64, u64), u64>> {
+    GENERATIONS.lock().unwrap_or_else(|e| e.into_inner())
+}
 
-    path.to_str().map(|s| s.to_string())
+fn assign_generation(metadata: &Metadata) {
+    let generation = NEXT_GENERATION.fetch_add(1, Ordering::Relaxed);
+    generations().insert(file_id(metadata), generation);
 }
 
-pub fn read_file_to_string(file_path: &Path) -> Option<String> {
-    match fs::read_to_string(file_path) {
-        Ok(content) => Some(content),
-        Err(_) => None,
+// Drop the generation of a file that is about to be replaced or deleted, unless another link
+// keeps it around
+async fn forget_generation(file_path: &Path) {
+    if let Ok(metadata) = tokio::fs::symlink_metadata(file_path).await {
+        if link_count(&metadata) <= 1 {
+            generations().remove(&file_id(&metadata));
+        }
     }
 }
 
-pub fn write_string_to_file(file_path: &Path, to_write: &str) -> io::Result<()> {
-    let mut data_file = File::create(file_path).expect("creation failed");
-    data_file.write(to_write.as_bytes()).expect("write failed");
+#[cfg(unix)]
+fn file_id(metadata: &Metadata) -> (u64, u64) {
+    use std::os::unix::fs::MetadataExt;
+    (metadata.dev(), metadata.ino())
+}
+
+#[cfg(unix)]
+fn link_count(metadata: &Metadata) -> u64 {
+    use std::os::unix::fs::MetadataExt;
+    metadata.nlink()
+}
+
+// Other platforms leave the inode, and with it the generation, out of the tag
+#[cfg(not(unix))]
+fn file_id(_metadata: &Metadata) -> (u64, u64) {
+    (0, 0)
+}
+
+#[cfg(not(unix))]
+fn link_count(_metadata: &Metadata) -> u64 {
+    1
+}
+
+// Remove a temporary file after a failed write, the original error is what gets reported
+async fn discard(temp_path: &Path, error: io::Error) -> io::Error {
+    let _ = tokio::fs::remove_file(temp_path).await;
+    error
+}
+
+#[cfg(unix)]
+async fn sync_directory(directory: &Path) -> io::Result<()> {
+    tokio::fs::File::open(directory).await?.sync_all().await
+}
+
+// Directories cannot be opened for syncing on other platforms
+#[cfg(not(unix))]
+async fn sync_directory(_directory: &Path) -> io::Result<()> {
     Ok(())
 }
+
+pub async fn delete_file(file_path: &Path) -> io::Result<()> {
+    forget_generation(file_path).await;
+    tokio::fs::remove_file(file_path).await?;
+    sync_directory(parent_directory(file_path)).await
+}
+
+// Status describing a failed file operation
+pub fn error_status(error: &io::Error) -> HTTPStatus {
+    match error.kind() {
+        ErrorKind::NotFound => HTTPStatus::NotFound,
+        ErrorKind::PermissionDenied | ErrorKind::ReadOnlyFilesystem => HTTPStatus::Forbidden,
+        // the path runs through a file, names a directory or the directory is not empty
+        ErrorKind::NotADirectory
+        | ErrorKind::IsADirectory
+        | ErrorKind::DirectoryNotEmpty
+        | ErrorKind::AlreadyExists => HTTPStatus::Conflict,
+        ErrorKind::StorageFull | ErrorKind::QuotaExceeded | ErrorKind::FileTooLarge => {
+            HTTPStatus::InsufficientStorage
+        }
+        _ => HTTPStatus::InternalServerError,
+    }
+}
+
+// Status for a failed write. A missing path there means the parent directory does not exist,
+// which is a conflict with the state of the store rather than a missing resource.
+pub fn write_error_status(error: &io::Error) -> HTTPStatus {
+    match error.kind() {
+        ErrorKind::NotFound => HTTPStatus::Conflict,
+        _ => error_status(error),
+    }
+}
+
+// Media type of a file from its extension, unknown types are served as opaque bytes
+pub fn mime_type(path: &Path) -> &'static str {
+    let extension = path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .map(|extension| extension.to_ascii_lowercase());
+
+    match extension.as_deref() {
+        Some("html" | "htm") => "text/html; charset=utf-8",
+        Some("css") => "text/css; charset=utf-8",
+        Some("js" | "mjs") => "text/javascript; charset=utf-8",
+        Some("txt" | "text" | "log") => "text/plain; charset=utf-8",
+        Some("md" | "markdown") => "text/markdown; charset=utf-8",
+        Some("csv") => "text/csv; charset=utf-8",
+        Some("xml") => "application/xml",
+        Some("json" | "map") => "application/json",
+        Some("wasm") => "application/wasm",
+        Some("pdf") => "application/pdf",
+        Some("zip") => "application/zip",
+        Some("gz") => "application/gzip",
+        Some("tar") => "application/x-tar",
+        Some("png") => "image/png",
+        Some("jpg" | "jpeg") => "image/jpeg",
+        Some("gif") => "image/gif",
+        Some("svg") => "image/svg+xml",
+        Some("webp") => "image/webp",
+        Some("avif") => "image/avif",
+        Some("ico") => "image/x-icon",
+        Some("woff") => "font/woff",
+        Some("woff2") => "font/woff2",
+        Some("ttf") => "font/ttf",
+        Some("otf") => "font/otf",
+        Some("mp3") => "audio/mpeg",
+        Some("ogg") => "audio/ogg",
+        Some("wav") => "audio/wav",
+        Some("mp4") => "video/mp4",
+        Some("webm") => "video/webm",
+        _ => "application/octet-stream",
+    }
+}
+
+// Validators derived from the file size, modification time and, on unix, the inode and the
+// generation of files written by this process. The generation changes on every write, so the
+// strong tag does too even when the timestamp does not move and an inode number is reused.
+pub fn validators(metadata: &Metadata) -> Validators {
+    let Ok(modified) = metadata.modified() else {
+        return Validators::default();
+    };
+    let since_epoch = modified.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let mut tag = format!("{:x}-", metadata.len());
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        tag.push_str(&format!("{:x}-", metadata.ino()));
+    }
+    tag.push_str(&format!(
+        "{:x}.{:x}",
+        since_epoch.as_secs(),
+        since_epoch.subsec_nanos()
+    ));
+    #[cfg(unix)]
+    if let Some(generation) = generations().get(&file_id(metadata)) {
+        tag.push_str(&format!("-{:x}", generation));
+    }
+
+    Validators {
+        etag: Some(EntityTag::strong(tag)),
+        last_modified: Some(modified),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::pin::Pin;
+    use std::task::{Context, Poll};
+    use tokio::io::{AsyncReadExt, ReadBuf};
+
+    #[test]
+    fn test_mime_type() {
+        assert_eq!(
+            mime_type(Path::new("index.HTML")),
+            "text/html; charset=utf-8"
+        );
+        assert_eq!(mime_type(Path::new("logo.png")), "image/png");
+        assert_eq!(mime_type(Path::new("archive.tar.gz")), "application/gzip");
+        assert_eq!(mime_type(Path::new("README")), "application/octet-stream");
+    }
+
+    // Source that breaks off like a client disconnecting mid-upload
+    struct FailingReader;
+
+    impl AsyncRead for FailingReader {
+        fn poll_read(
+            self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+            _buf: &mut ReadBuf<'_>,
+        ) -> Poll<io::Result<()>> {
+            Poll::Ready(Err(io::Error::new(ErrorKind::ConnectionReset, "reset")))
+        }
+    }
+
+    // Names in `directory`, temporary files left behind would show up here
+    fn entries(directory: &Path) -> Vec<String> {
+        let mut names: Vec<String> = std::fs::read_dir(directory)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[tokio::test]
+    async fn test_write_file_atomic() {
+        let temp = tempfile::tempdir().unwrap();
+        let directory = temp.path();
+        let path = directory.join("a.txt");
+
+        let written = write_file_atomic(&path, &mut &b"first"[..], false)
+            .await
+            .unwrap();
+        assert_eq!(written, 5);
+        write_file_atomic(&path, &mut &b"second"[..], false)
+            .await
+            .unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "second");
+        assert_eq!(entries(directory), vec!["a.txt"]);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_replace_keeps_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+        let temp = tempfile::tempdir().unwrap();
+        let directory = temp.path();
+        let path = directory.join("secret.txt");
+        let mode = |path: &Path| std::fs::metadata(path).unwrap().permissions().mode() & 0o777;
+        std::fs::write(&path, "old").unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o600)).unwrap();
+
+        write_file_atomic(&path, &mut &b"new"[..], false)
+            .await
+            .unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "new");
+        assert_eq!(mode(&path), 0o600);
+        update_file_atomic(&path, None, b"er").await.unwrap();
+        assert_eq!(mode(&path), 0o600);
+    }
+
+    #[tokio::test]
+    async fn test_write_file_atomic_parent_directories() {
+        let temp = tempfile::tempdir().unwrap();
+        let directory = temp.path();
+        let path = directory.join("a/b/c.txt");
+
+        let error = write_file_atomic(&path, &mut &b"data"[..], false)
+            .await
+            .unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::NotFound);
+        assert_eq!(write_error_status(&error), HTTPStatus::Conflict);
+
+        write_file_atomic(&path, &mut &b"data"[..], true)
+            .await
+            .unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "data");
+
+        // a file where a directory is expected
+        let error = write_file_atomic(&path.join("d.txt"), &mut &b"data"[..], true)
+            .await
+            .unwrap_err();
+        assert_eq!(write_error_status(&error), HTTPStatus::Conflict);
+    }
+
+    #[tokio::test]
+    async fn test_failed_write_keeps_original() {
+        let temp = tempfile::tempdir().unwrap();
+        let directory = temp.path();
+        let path = directory.join("a.txt");
+        std::fs::write(&path, "original").unwrap();
+
+        // a source failing halfway through must not leave a truncated file behind
+        let mut source = (&b"partial"[..]).chain(FailingReader);
+        assert!(write_file_atomic(&path, &mut source, false).await.is_err());
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "original");
+        assert_eq!(entries(directory), vec!["a.txt"]);
+    }
+
+    #[tokio::test]
+    async fn test_update_file_atomic() {
+        let temp = tempfile::tempdir().unwrap();
+        let directory = temp.path();
+        let path = directory.join("a.txt");
+        std::fs::write(&path, "hello").unwrap();
+
+        update_file_atomic(&path, None, b" world").await.unwrap();
+        update_file_atomic(&path, Some(0), b"HELLO").await.unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "HELLO world");
+        assert_eq!(entries(directory), vec!["a.txt"]);
+
+        let error = update_file_atomic(&directory.join("missing"), None, b"x")
+            .await
+            .unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::NotFound);
+        assert_eq!(entries(directory), vec!["a.txt"]);
+    }
+
+    #[test]
+    fn test_error_status() {
+        let status = |kind: ErrorKind| error_status(&io::Error::from(kind));
+        assert_eq!(status(ErrorKind::NotFound), HTTPStatus::NotFound);
+        assert_eq!(status(ErrorKind::PermissionDenied), HTTPStatus::Forbidden);
+        assert_eq!(status(ErrorKind::IsADirectory), HTTPStatus::Conflict);
+        assert_eq!(
+            status(ErrorKind::StorageFull),
+            HTTPStatus::InsufficientStorage
+        );
+        assert_eq!(status(ErrorKind::Other), HTTPStatus::InternalServerError);
+    }
+
+    #[tokio::test]
+    async fn test_validators_change_on_every_write() {
+        let temp = tempfile::tempdir().unwrap();
+        let directory = temp.path();
+        let path = directory.join("a.txt");
+        write_file_atomic(&path, &mut &b"data"[..], false)
+            .await
+            .unwrap();
+        let first = validators(&std::fs::metadata(&path).unwrap());
+        let first = first.etag.unwrap();
+        assert!(!first.weak);
+        assert!(first.tag.starts_with("4-"));
+
+        // same size and possibly the same timestamp, still a different version
+        write_file_atomic(&path, &mut &b"DATA"[..], false)
+            .await
+            .unwrap();
+        let second = validators(&std::fs::metadata(&path).unwrap());
+        let second = second.etag.unwrap();
+        assert!(!second.weak);
+        #[cfg(unix)]
+        assert_ne!(first, second);
+
+        // a later write that lands on a reused inode with the same size and timestamp
+        let metadata = std::fs::metadata(&path).unwrap();
+        assign_generation(&metadata);
+        let third = validators(&metadata).etag.unwrap();
+        #[cfg(unix)]
+        assert_ne!(second, third);
+
+        // deleting forgets the generation
+        delete_file(&path).await.unwrap();
+        assert!(!generations().contains_key(&file_id(&metadata)));
+    }
+}
@@ -0,0 +1,169 @@
+use crate::request::ParsedRequest;
+use crate::response::HTTPResponse;
+
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+
+pub type HandlerFuture = Pin<Box<dyn Future<Output = HTTPResponse> + Send>>;
+
+// Anything that turns a request into a response. Implemented for async functions and closures
+// taking a `ParsedRequest`, for the `Router` and for handlers wrapped in middleware.
+pub trait Handler: Send + Sync + 'static {
+    fn call(&self, request: ParsedRequest) -> HandlerFuture;
+}
+
+impl<F, Fut> Handler for F
+where
+    F: Fn(ParsedRequest) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = HTTPResponse> + Send + 'static,
+{
+    fn call(&self, request: ParsedRequest) -> HandlerFuture {
+        Box::pin(self(request))
+    }
+}
+
+// Code that runs around a handler. It can inspect or change the request before passing it on
+// with `next.run(request)`, change the response on the way back, or answer on its own without
+// calling `next` at all (e.g. rejecting an unauthenticated request).
+pub trait Middleware: Send + Sync + 'static {
+    fn handle(&self, request: ParsedRequest, next: Next) -> HandlerFuture;
+}
+
+impl<F, Fut> Middleware for F
+where
+    F: Fn(ParsedRequest, Next) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = HTTPResponse> + Send + 'static,
+{
+    fn handle(&self, request: ParsedRequest, next: Next) -> HandlerFuture {
+        Box::pin(self(request, next))
+    }
+}
+
+pub type MiddlewareStack = Arc<Vec<Arc<dyn Middleware>>>;
+
+// The rest of the chain from the point of view of a middleware
+pub struct Next {
+    middleware: MiddlewareStack,
+    index: usize,
+    endpoint: Arc<dyn Handler>,
+}
+
+impl Next {
+    pub fn new(middleware: MiddlewareStack, endpoint: Arc<dyn Handler>) -> Self {
+        Next {
+            middleware,
+            index: 0,
+            endpoint,
+        }
+    }
+
+    pub fn run(self, request: ParsedRequest) -> HandlerFuture {
+        match self.middleware.get(self.index).cloned() {
+            Some(middleware) => middleware.handle(
+                request,
+                Next {
+                    index: self.index + 1,
+                    ..self
+                },
+            ),
+            None => self.endpoint.call(request),
+        }
+    }
+}
+
+// A handler with middleware around it. Middleware runs in the order it was added, the first
+// one sees the request first and the response last.
+#[derive(Clone)]
+pub struct Layered {
+    middleware: MiddlewareStack,
+    inner: Arc<dyn Handler>,
+}
+
+impl Layered {
+    pub fn layer<M: Middleware>(mut self, middleware: M) -> Self {
+        Arc::make_mut(&mut self.middleware).push(Arc::new(middleware));
+        self
+    }
+}
+
+impl Handler for Layered {
+    fn call(&self, request: ParsedRequest) -> HandlerFuture {
+        Next::new(self.middleware.clone(), self.inner.clone()).run(request)
+    }
+}
+
+pub trait HandlerExt: Handler + Sized {
+    // Wrap a single handler, e.g. `router.get("/admin", admin.layer(require_auth))`
+    fn layer<M: Middleware>(self, middleware: M) -> Layered {
+        Layered {
+            middleware: Arc::new(vec![Arc::new(middleware)]),
+            inner: Arc::new(self),
+        }
+    }
+}
+
+impl<H: Handler> HandlerExt for H {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::{HTTPBody, HTTPStatus};
+    use crate::request::parse_stream;
+
+    async fn request(raw: &str) -> ParsedRequest {
+        parse_stream(&mut raw.as_bytes()).await.unwrap()
+    }
+
+    async fn hello(_request: ParsedRequest) -> HTTPResponse {
+        HTTPResponse::builder()
+            .body(HTTPBody::text("hello"))
+            .build()
+    }
+
+    async fn add_header(request: ParsedRequest, next: Next) -> HTTPResponse {
+        let mut response = next.run(request).await;
+        let seen = response.headers.get_all("X-Seen").count();
+        response
+            .headers
+            .append("X-Seen", &format!("layer-{}", seen + 1));
+        response
+    }
+
+    async fn require_auth(request: ParsedRequest, next: Next) -> HTTPResponse {
+        if request.headers.authorization() != Some("Bearer secret") {
+            return HTTPResponse::new(HTTPStatus::Unauthorized);
+        }
+        next.run(request).await
+    }
+
+    #[tokio::test]
+    async fn test_function_is_handler() {
+        let response = Handler::call(&hello, request("GET / HTTP/1.1\r\n\r\n").await).await;
+        assert_eq!(response.status, HTTPStatus::Ok);
+    }
+
+    #[tokio::test]
+    async fn test_middleware_order() {
+        let handler = hello.layer(add_header).layer(add_header);
+        let response = handler.call(request("GET / HTTP/1.1\r\n\r\n").await).await;
+
+        // the innermost layer adds its header first
+        assert_eq!(
+            response.headers.get_all("x-seen").collect::<Vec<_>>(),
+            vec!["layer-1", "layer-2"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_middleware_short_circuits() {
+        let handler = hello.layer(require_auth);
+
+        let response = handler.call(request("GET / HTTP/1.1\r\n\r\n").await).await;
+        assert_eq!(response.status, HTTPStatus::Unauthorized);
+
+        let raw = "GET / HTTP/1.1\r\nAuthorization: Bearer secret\r\n\r\n";
+        let response = handler.call(request(raw).await).await;
+        assert_eq!(response.status, HTTPStatus::Ok);
+    }
+}
@@ -1,49 +1,314 @@
+use crate::body::Body;
+
+use bytes::Bytes;
 use std::fmt;
+use std::io;
+use thiserror::Error;
+use tokio::fs::File;
+use tokio::io::AsyncRead;
 
-pub enum HTTPStatus {
-    Ok,
-    Created,
-    BadRequest,
-    NotFound,
-    InternalServerError,
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Method {
+    Get,
+    Head,
+    Post,
+    Put,
+    Delete,
+    Connect,
+    Options,
+    Trace,
+    Patch,
+    // Any other syntactically valid method token, e.g. PROPFIND or MKCOL
+    Extension(String),
 }
 
-impl HTTPStatus {
-    fn status_code(&self) -> u16 {
+impl Method {
+    // Methods are case-sensitive tokens (RFC 9110 section 9.1), so `get` is an extension method
+    pub fn from_token(token: &str) -> Option<Method> {
+        if !is_token(token) {
+            return None;
+        }
+        let method = match token {
+            "GET" => Method::Get,
+            "HEAD" => Method::Head,
+            "POST" => Method::Post,
+            "PUT" => Method::Put,
+            "DELETE" => Method::Delete,
+            "CONNECT" => Method::Connect,
+            "OPTIONS" => Method::Options,
+            "TRACE" => Method::Trace,
+            "PATCH" => Method::Patch,
+            other => Method::Extension(other.to_string()),
+        };
+        Some(method)
+    }
+
+    pub fn as_str(&self) -> &str {
         match self {
-            HTTPStatus::Ok => 200,
-            HTTPStatus::Created => 201,
-            HTTPStatus::BadRequest => 400,
-            HTTPStatus::NotFound => 404,
-            HTTPStatus::InternalServerError => 500,
+            Method::Get => "GET",
+            Method::Head => "HEAD",
+            Method::Post => "POST",
+            Method::Put => "PUT",
+            Method::Delete => "DELETE",
+            Method::Connect => "CONNECT",
+            Method::Options => "OPTIONS",
+            Method::Trace => "TRACE",
+            Method::Patch => "PATCH",
+            Method::Extension(token) => token,
+        }
+    }
+}
+
+impl fmt::Display for Method {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Version {
+    Http10,
+    #[default]
+    Http11,
+}
+
+impl Version {
+    pub fn parse(version: &str) -> Option<Version> {
+        match version {
+            "HTTP/1.0" => Some(Version::Http10),
+            "HTTP/1.1" => Some(Version::Http11),
+            _ => None,
         }
     }
 
-    fn reason_phrase(&self) -> &str {
+    pub fn as_str(&self) -> &str {
         match self {
-            HTTPStatus::Ok => "OK",
-            HTTPStatus::Created => "Created",
-            HTTPStatus::BadRequest => "Bad Request",
-            HTTPStatus::NotFound => "Not Found",
-            HTTPStatus::InternalServerError => "Internal Server Error",
+            Version::Http10 => "HTTP/1.0",
+            Version::Http11 => "HTTP/1.1",
         }
     }
 }
 
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+// tchar as defined in RFC 9110 section 5.6.2
+pub fn is_tchar(c: u8) -> bool {
+    c.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&c)
+}
+
+pub fn is_token(s: &str) -> bool {
+    !s.is_empty() && s.bytes().all(is_tchar)
+}
+
+fn is_valid_header(name: &str, value: &str) -> bool {
+    is_token(name) && !value.contains(['\r', '\n', '\0'])
+}
+
+// Decode %XX escapes, None if an escape is truncated or not hexadecimal
+pub fn percent_decode(s: &str) -> Option<Vec<u8>> {
+    let bytes = s.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = bytes.get(i + 1..i + 3)?;
+            let hex = std::str::from_utf8(hex).ok()?;
+            if !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+                return None;
+            }
+            decoded.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+    Some(decoded)
+}
+
+// Generates the HTTPStatus enum together with its code and reason phrase lookups
+// from a single table, so the three can never disagree.
+macro_rules! http_statuses {
+    ($($variant:ident, $code:literal, $reason:literal;)+) => {
+        #[derive(Debug, Clone, PartialEq, Eq)]
+        pub enum HTTPStatus {
+            $($variant,)+
+            // Any status code outside the registry, with its reason phrase
+            Custom(u16, String),
+        }
+
+        impl HTTPStatus {
+            pub fn status_code(&self) -> u16 {
+                match self {
+                    $(HTTPStatus::$variant => $code,)+
+                    HTTPStatus::Custom(code, _) => *code,
+                }
+            }
+
+            pub fn reason_phrase(&self) -> &str {
+                match self {
+                    $(HTTPStatus::$variant => $reason,)+
+                    HTTPStatus::Custom(_, reason) => reason,
+                }
+            }
+
+            // Registered status for the code, or None if IANA does not define it
+            pub fn from_code(code: u16) -> Option<HTTPStatus> {
+                match code {
+                    $($code => Some(HTTPStatus::$variant),)+
+                    _ => None,
+                }
+            }
+        }
+    };
+}
+
+// IANA HTTP Status Code Registry
+http_statuses! {
+    Continue, 100, "Continue";
+    SwitchingProtocols, 101, "Switching Protocols";
+    Processing, 102, "Processing";
+    EarlyHints, 103, "Early Hints";
+    Ok, 200, "OK";
+    Created, 201, "Created";
+    Accepted, 202, "Accepted";
+    NonAuthoritativeInformation, 203, "Non-Authoritative Information";
+    NoContent, 204, "No Content";
+    ResetContent, 205, "Reset Content";
+    PartialContent, 206, "Partial Content";
+    MultiStatus, 207, "Multi-Status";
+    AlreadyReported, 208, "Already Reported";
+    ImUsed, 226, "IM Used";
+    MultipleChoices, 300, "Multiple Choices";
+    MovedPermanently, 301, "Moved Permanently";
+    Found, 302, "Found";
+    SeeOther, 303, "See Other";
+    NotModified, 304, "Not Modified";
+    UseProxy, 305, "Use Proxy";
+    TemporaryRedirect, 307, "Temporary Redirect";
+    PermanentRedirect, 308, "Permanent Redirect";
+    BadRequest, 400, "Bad Request";
+    Unauthorized, 401, "Unauthorized";
+    PaymentRequired, 402, "Payment Required";
+    Forbidden, 403, "Forbidden";
+    NotFound, 404, "Not Found";
+    MethodNotAllowed, 405, "Method Not Allowed";
+    NotAcceptable, 406, "Not Acceptable";
+    ProxyAuthenticationRequired, 407, "Proxy Authentication Required";
+    RequestTimeout, 408, "Request Timeout";
+    Conflict, 409, "Conflict";
+    Gone, 410, "Gone";
+    LengthRequired, 411, "Length Required";
+    PreconditionFailed, 412, "Precondition Failed";
+    ContentTooLarge, 413, "Content Too Large";
+    UriTooLong, 414, "URI Too Long";
+    UnsupportedMediaType, 415, "Unsupported Media Type";
+    RangeNotSatisfiable, 416, "Range Not Satisfiable";
+    ExpectationFailed, 417, "Expectation Failed";
+    ImATeapot, 418, "I'm a teapot";
+    MisdirectedRequest, 421, "Misdirected Request";
+    UnprocessableContent, 422, "Unprocessable Content";
+    Locked, 423, "Locked";
+    FailedDependency, 424, "Failed Dependency";
+    TooEarly, 425, "Too Early";
+    UpgradeRequired, 426, "Upgrade Required";
+    PreconditionRequired, 428, "Precondition Required";
+    TooManyRequests, 429, "Too Many Requests";
+    RequestHeaderFieldsTooLarge, 431, "Request Header Fields Too Large";
+    UnavailableForLegalReasons, 451, "Unavailable For Legal Reasons";
+    InternalServerError, 500, "Internal Server Error";
+    NotImplemented, 501, "Not Implemented";
+    BadGateway, 502, "Bad Gateway";
+    ServiceUnavailable, 503, "Service Unavailable";
+    GatewayTimeout, 504, "Gateway Timeout";
+    HttpVersionNotSupported, 505, "HTTP Version Not Supported";
+    VariantAlsoNegotiates, 506, "Variant Also Negotiates";
+    InsufficientStorage, 507, "Insufficient Storage";
+    LoopDetected, 508, "Loop Detected";
+    NotExtended, 510, "Not Extended";
+    NetworkAuthenticationRequired, 511, "Network Authentication Required";
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+#[error("invalid status code {0}, must be between 100 and 999")]
+pub struct InvalidStatusCode(pub u16);
+
+impl HTTPStatus {
+    pub fn is_informational(&self) -> bool {
+        (100..200).contains(&self.status_code())
+    }
+
+    pub fn is_success(&self) -> bool {
+        (200..300).contains(&self.status_code())
+    }
+
+    pub fn is_redirect(&self) -> bool {
+        (300..400).contains(&self.status_code())
+    }
+
+    pub fn is_client_error(&self) -> bool {
+        (400..500).contains(&self.status_code())
+    }
+
+    pub fn is_server_error(&self) -> bool {
+        (500..600).contains(&self.status_code())
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.is_client_error() || self.is_server_error()
+    }
+
+    // 1xx, 204 and 304 responses never carry content (RFC 9110 section 6.4.1)
+    pub fn allows_body(&self) -> bool {
+        !self.is_informational() && !matches!(self.status_code(), 204 | 304)
+    }
+
+    // Status line without the trailing CRLF, e.g. `HTTP/1.0 404 Not Found`
+    pub fn status_line(&self, version: Version) -> String {
+        format!("{} {}", version, self)
+    }
+}
+
+// Unregistered codes become `Custom` with an empty reason phrase
+impl TryFrom<u16> for HTTPStatus {
+    type Error = InvalidStatusCode;
+
+    fn try_from(code: u16) -> Result<Self, Self::Error> {
+        if !(100..=999).contains(&code) {
+            return Err(InvalidStatusCode(code));
+        }
+        Ok(HTTPStatus::from_code(code).unwrap_or(HTTPStatus::Custom(code, String::new())))
+    }
+}
+
+impl From<&HTTPStatus> for u16 {
+    fn from(status: &HTTPStatus) -> u16 {
+        status.status_code()
+    }
+}
+
+impl From<HTTPStatus> for u16 {
+    fn from(status: HTTPStatus) -> u16 {
+        status.status_code()
+    }
+}
+
+// Code and reason phrase, the protocol version is added by `status_line`
 impl fmt::Display for HTTPStatus {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(
-            f,
-            "HTTP/1.1 {} {}",
-            self.status_code(),
-            self.reason_phrase()
-        )
+        write!(f, "{} {}", self.status_code(), self.reason_phrase())
     }
 }
 
 pub enum HTTPContentType {
     PlainText,
     File,
+    // any other media type, e.g. "text/html; charset=utf-8"
+    Custom(String),
 }
 
 impl HTTPContentType {
@@ -51,6 +316,7 @@ impl HTTPContentType {
         match self {
             HTTPContentType::PlainText => "text/plain",
             HTTPContentType::File => "application/octet-stream",
+            HTTPContentType::Custom(media_type) => media_type,
         }
     }
 }
@@ -62,14 +328,166 @@ impl fmt::Display for HTTPContentType {
 }
 
 pub struct HTTPBody {
-    pub body: String,
+    pub body: Body,
     pub content_type: HTTPContentType,
 }
 
+impl HTTPBody {
+    pub fn text(body: impl Into<String>) -> Self {
+        HTTPBody {
+            body: Body::from(body.into()),
+            content_type: HTTPContentType::PlainText,
+        }
+    }
+
+    pub fn file(body: impl Into<Bytes>) -> Self {
+        HTTPBody {
+            body: Body::Full(body.into()),
+            content_type: HTTPContentType::File,
+        }
+    }
+
+    // Body streamed from an open file, the file is read as the client consumes it
+    pub async fn from_file(file: File) -> io::Result<Self> {
+        Ok(HTTPBody {
+            body: Body::file(file).await?,
+            content_type: HTTPContentType::File,
+        })
+    }
+
+    pub fn stream<R>(reader: R, length: Option<u64>, content_type: HTTPContentType) -> Self
+    where
+        R: AsyncRead + Send + 'static,
+    {
+        HTTPBody {
+            body: Body::stream(reader, length),
+            content_type,
+        }
+    }
+}
+
+// Case-insensitive, multi-value header collection. Headers are kept in the order
+// they were received and duplicates are preserved, names keep their original casing.
+// Headers that would break the message framing when written, an invalid name or a CR, LF or
+// NUL in the value, are never stored.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HeaderMap {
+    entries: Vec<(String, String)>,
+}
+
+impl HeaderMap {
+    pub fn new() -> Self {
+        HeaderMap {
+            entries: Vec::new(),
+        }
+    }
+
+    // Add a header without touching existing values of the same name, false if it is invalid
+    // and was dropped
+    pub fn append(&mut self, name: &str, value: &str) -> bool {
+        if !is_valid_header(name, value) {
+            return false;
+        }
+        self.entries.push((name.to_string(), value.to_string()));
+        true
+    }
+
+    // Replace all existing values of a header with a single value, an invalid header leaves the
+    // existing values in place
+    pub fn insert(&mut self, name: &str, value: &str) -> bool {
+        if !is_valid_header(name, value) {
+            return false;
+        }
+        self.remove(name);
+        self.append(name, value)
+    }
+
+    pub fn remove(&mut self, name: &str) {
+        self.entries
+            .retain(|(key, _)| !key.eq_ignore_ascii_case(name));
+    }
+
+    // First value for the header, if present
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.entries
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn get_all<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.entries
+            .iter()
+            .filter(move |(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.get(name).is_some()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.entries
+            .iter()
+            .map(|(key, value)| (key.as_str(), value.as_str()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_method_from_token() {
+        assert_eq!(Method::from_token("GET"), Some(Method::Get));
+        assert_eq!(Method::from_token("HEAD"), Some(Method::Head));
+        assert_eq!(Method::from_token("PUT"), Some(Method::Put));
+        assert_eq!(Method::from_token("DELETE"), Some(Method::Delete));
+        assert_eq!(Method::from_token("OPTIONS"), Some(Method::Options));
+        assert_eq!(Method::from_token("PATCH"), Some(Method::Patch));
+        assert_eq!(
+            Method::from_token("PROPFIND"),
+            Some(Method::Extension("PROPFIND".to_string()))
+        );
+        // methods are case-sensitive
+        assert_eq!(
+            Method::from_token("get"),
+            Some(Method::Extension("get".to_string()))
+        );
+        assert_eq!(Method::from_token(""), None);
+        assert_eq!(Method::from_token("GE T"), None);
+        assert_eq!(Method::from_token("GET/"), None);
+        assert_eq!(Method::Extension("MKCOL".to_string()).to_string(), "MKCOL");
+    }
+
+    #[test]
+    fn test_version_parse() {
+        assert_eq!(Version::parse("HTTP/1.0"), Some(Version::Http10));
+        assert_eq!(Version::parse("HTTP/1.1"), Some(Version::Http11));
+        assert_eq!(Version::parse("HTTP/2.0"), None);
+        assert_eq!(Version::parse("http/1.1"), None);
+        assert_eq!(Version::Http10.to_string(), "HTTP/1.0");
+    }
+
+    #[test]
+    fn test_percent_decode() {
+        assert_eq!(percent_decode("a%20b%2Fc").unwrap(), b"a b/c");
+        assert_eq!(percent_decode("%e2%9c%93").unwrap(), "\u{2713}".as_bytes());
+        assert_eq!(percent_decode("plain+text").unwrap(), b"plain+text");
+        assert_eq!(percent_decode("%"), None);
+        assert_eq!(percent_decode("%4"), None);
+        assert_eq!(percent_decode("%zz"), None);
+        assert_eq!(percent_decode("%+1"), None);
+    }
+
     #[test]
     fn test_http_status_code_and_phrase() {
         assert_eq!(HTTPStatus::Ok.status_code(), 200);
@@ -84,6 +502,27 @@ mod tests {
         assert_eq!(HTTPStatus::NotFound.status_code(), 404);
         assert_eq!(HTTPStatus::NotFound.reason_phrase(), "Not Found");
 
+        assert_eq!(HTTPStatus::UriTooLong.status_code(), 414);
+        assert_eq!(HTTPStatus::UriTooLong.reason_phrase(), "URI Too Long");
+
+        assert_eq!(HTTPStatus::RequestHeaderFieldsTooLarge.status_code(), 431);
+        assert_eq!(
+            HTTPStatus::RequestHeaderFieldsTooLarge.reason_phrase(),
+            "Request Header Fields Too Large"
+        );
+
+        assert_eq!(HTTPStatus::NotImplemented.status_code(), 501);
+        assert_eq!(
+            HTTPStatus::NotImplemented.reason_phrase(),
+            "Not Implemented"
+        );
+
+        assert_eq!(HTTPStatus::HttpVersionNotSupported.status_code(), 505);
+        assert_eq!(
+            HTTPStatus::HttpVersionNotSupported.reason_phrase(),
+            "HTTP Version Not Supported"
+        );
+
         assert_eq!(HTTPStatus::InternalServerError.status_code(), 500);
         assert_eq!(
             HTTPStatus::InternalServerError.reason_phrase(),
@@ -93,21 +532,76 @@ mod tests {
 
     #[test]
     fn http_status_display_format() {
-        assert_eq!(format!("{}", HTTPStatus::Ok), "HTTP/1.1 200 OK");
-        assert_eq!(format!("{}", HTTPStatus::Created), "HTTP/1.1 201 Created");
+        assert_eq!(format!("{}", HTTPStatus::Ok), "200 OK");
+        assert_eq!(format!("{}", HTTPStatus::Created), "201 Created");
+        assert_eq!(format!("{}", HTTPStatus::BadRequest), "400 Bad Request");
+        assert_eq!(format!("{}", HTTPStatus::NotFound), "404 Not Found");
         assert_eq!(
-            format!("{}", HTTPStatus::BadRequest),
-            "HTTP/1.1 400 Bad Request"
+            format!("{}", HTTPStatus::InternalServerError),
+            "500 Internal Server Error"
         );
         assert_eq!(
-            format!("{}", HTTPStatus::NotFound),
-            "HTTP/1.1 404 Not Found"
+            format!("{}", HTTPStatus::Custom(599, "Network Timeout".to_string())),
+            "599 Network Timeout"
         );
+    }
+
+    #[test]
+    fn http_status_line_uses_version() {
         assert_eq!(
-            format!("{}", HTTPStatus::InternalServerError),
-            "HTTP/1.1 500 Internal Server Error"
+            HTTPStatus::Ok.status_line(Version::Http11),
+            "HTTP/1.1 200 OK"
+        );
+        assert_eq!(
+            HTTPStatus::NotFound.status_line(Version::Http10),
+            "HTTP/1.0 404 Not Found"
         );
     }
+
+    #[test]
+    fn test_http_status_u16_conversions() {
+        assert_eq!(HTTPStatus::try_from(204), Ok(HTTPStatus::NoContent));
+        assert_eq!(HTTPStatus::try_from(308), Ok(HTTPStatus::PermanentRedirect));
+        assert_eq!(HTTPStatus::try_from(429), Ok(HTTPStatus::TooManyRequests));
+        assert_eq!(
+            HTTPStatus::try_from(299),
+            Ok(HTTPStatus::Custom(299, String::new()))
+        );
+        assert_eq!(HTTPStatus::try_from(99), Err(InvalidStatusCode(99)));
+        assert_eq!(HTTPStatus::try_from(1000), Err(InvalidStatusCode(1000)));
+        assert_eq!(HTTPStatus::from_code(418), Some(HTTPStatus::ImATeapot));
+        assert_eq!(HTTPStatus::from_code(420), None);
+
+        assert_eq!(u16::from(HTTPStatus::ServiceUnavailable), 503);
+        assert_eq!(u16::from(&HTTPStatus::Custom(799, "x".to_string())), 799);
+
+        // every registered code maps back to itself
+        for code in 100..600 {
+            if let Some(status) = HTTPStatus::from_code(code) {
+                assert_eq!(status.status_code(), code);
+                assert!(!status.reason_phrase().is_empty());
+            }
+        }
+    }
+
+    #[test]
+    fn test_http_status_classification() {
+        assert!(HTTPStatus::Continue.is_informational());
+        assert!(HTTPStatus::NoContent.is_success());
+        assert!(HTTPStatus::MovedPermanently.is_redirect());
+        assert!(HTTPStatus::NotModified.is_redirect());
+        assert!(HTTPStatus::MethodNotAllowed.is_client_error());
+        assert!(HTTPStatus::MethodNotAllowed.is_error());
+        assert!(HTTPStatus::ServiceUnavailable.is_server_error());
+        assert!(!HTTPStatus::Ok.is_error());
+        assert!(!HTTPStatus::Ok.is_redirect());
+
+        assert!(HTTPStatus::Ok.allows_body());
+        assert!(!HTTPStatus::NoContent.allows_body());
+        assert!(!HTTPStatus::NotModified.allows_body());
+        assert!(!HTTPStatus::SwitchingProtocols.allows_body());
+    }
+
     #[test]
     fn test_http_content_type() {
         assert_eq!(
@@ -128,4 +622,60 @@ mod tests {
             "Content-Type: text/plain"
         );
     }
+
+    #[test]
+    fn test_header_map_case_insensitive_lookup() {
+        let mut headers = HeaderMap::new();
+        headers.append("Content-Type", "text/plain");
+
+        assert_eq!(headers.get("content-type"), Some("text/plain"));
+        assert_eq!(headers.get("CONTENT-TYPE"), Some("text/plain"));
+        assert!(headers.contains("Content-type"));
+        assert_eq!(headers.get("Accept"), None);
+    }
+
+    #[test]
+    fn test_header_map_preserves_duplicates_and_order() {
+        let mut headers = HeaderMap::new();
+        headers.append("Cookie", "a=1");
+        headers.append("Host", "localhost");
+        headers.append("cookie", "b=2");
+
+        assert_eq!(headers.len(), 3);
+        assert_eq!(headers.get("Cookie"), Some("a=1"));
+        assert_eq!(
+            headers.get_all("COOKIE").collect::<Vec<_>>(),
+            vec!["a=1", "b=2"]
+        );
+        assert_eq!(
+            headers.iter().map(|(key, _)| key).collect::<Vec<_>>(),
+            vec!["Cookie", "Host", "cookie"]
+        );
+    }
+
+    #[test]
+    fn test_header_map_insert_replaces_all_values() {
+        let mut headers = HeaderMap::new();
+        headers.append("X-Trace", "1");
+        headers.append("x-trace", "2");
+        headers.insert("X-Trace", "3");
+
+        assert_eq!(headers.get_all("x-trace").collect::<Vec<_>>(), vec!["3"]);
+
+        headers.remove("X-TRACE");
+        assert!(headers.is_empty());
+    }
+
+    #[test]
+    fn test_header_map_rejects_injection() {
+        let mut headers = HeaderMap::new();
+        assert!(headers.append("Location", "/a"));
+        assert!(!headers.append("Location", "/a\r\nSet-Cookie: x=1"));
+        assert!(!headers.append("Bad Name", "x"));
+        assert!(!headers.append("", "x"));
+        assert!(!headers.insert("Location", "/b\n"));
+        assert!(!headers.insert("X-Null", "a\0b"));
+        assert_eq!(headers.get_all("Location").collect::<Vec<_>>(), vec!["/a"]);
+        assert_eq!(headers.len(), 1);
+    }
 }
@@ -0,0 +1,40 @@
+// HTTP/1.1 server that can be embedded in other programs, e.g.
+//
+//     let server = Server::builder()
+//         .settings(settings)
+//         .router(Router::new().get("/", index))
+//         .shutdown(async { stop.await.ok(); })
+//         .bind()
+//         .await?;
+//     let exit = server.run().await;
+
+pub mod body;
+pub mod conditional;
+pub mod config;
+pub mod connection;
+pub mod date;
+pub mod file;
+pub mod handler;
+pub mod http;
+pub mod log;
+pub mod parser;
+pub mod range;
+pub mod request;
+pub mod response;
+pub mod router;
+pub mod sandbox;
+pub mod server;
+pub mod shutdown;
+pub mod static_files;
+pub mod uri;
+
+pub use config::Settings;
+pub use handler::{Handler, HandlerExt, Middleware, Next};
+pub use http::{HTTPBody, HTTPStatus, HeaderMap, Method, Version};
+pub use request::ParsedRequest;
+pub use response::HTTPResponse;
+pub use router::Router;
+pub use server::{ExitReason, Server, ServerBuilder, ServerExit, ServerStatus};
+pub use shutdown::ShutdownSignal;
+pub use static_files::StaticFiles;
+pub use uri::Uri;
@@ -0,0 +1,204 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::OnceLock;
+use std::thread;
+use tokio::sync::oneshot;
+
+// Log of everything the server reports. Lines go to stdout, and errors to stderr, unless
+// `log_to` names a file, which then gets both and can be reopened after it was rotated.
+// A dedicated thread does the writing, so a slow disk never holds up the async runtime.
+
+pub struct LogFile {
+    path: PathBuf,
+    file: File,
+}
+
+impl LogFile {
+    pub fn open(path: impl Into<PathBuf>) -> io::Result<Self> {
+        let path = path.into();
+        let file = append(&path)?;
+        Ok(LogFile { path, file })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    // Open the path again, after a rotation it names a new file while the old handle still
+    // writes to the renamed one
+    pub fn reopen(&mut self) -> io::Result<()> {
+        self.file = append(&self.path)?;
+        Ok(())
+    }
+
+    pub fn write_line(&mut self, line: &str) -> io::Result<()> {
+        writeln!(self.file, "{}", line)
+    }
+}
+
+fn append(path: &Path) -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+enum Command {
+    Line { line: String, error: bool },
+    LogTo(LogFile),
+    Reopen(oneshot::Sender<io::Result<Option<PathBuf>>>),
+    Flush(mpsc::Sender<()>),
+}
+
+fn writer() -> &'static Sender<Command> {
+    static WRITER: OnceLock<Sender<Command>> = OnceLock::new();
+    WRITER.get_or_init(|| {
+        let (tx, rx) = mpsc::channel();
+        thread::Builder::new()
+            .name("log-writer".to_string())
+            .spawn(move || run_writer(rx))
+            .expect("Failed to start the log writer");
+        tx
+    })
+}
+
+// Handles commands in the order they were sent until every sender is gone
+fn run_writer(commands: Receiver<Command>) {
+    let mut log_file: Option<LogFile> = None;
+    for command in commands {
+        match command {
+            Command::Line { line, error } => match log_file.as_mut() {
+                Some(log_file) => {
+                    // losing a log line is no reason to fail a request
+                    if let Err(e) = log_file.write_line(&line) {
+                        eprintln!("Failed to write to {}: {}", log_file.path().display(), e);
+                        eprintln!("{}", line);
+                    }
+                }
+                None if error => eprintln!("{}", line),
+                None => println!("{}", line),
+            },
+            Command::LogTo(new_file) => log_file = Some(new_file),
+            Command::Reopen(reply) => {
+                let reopened = match log_file.as_mut() {
+                    Some(log_file) => log_file
+                        .reopen()
+                        .map(|()| Some(log_file.path().to_path_buf())),
+                    None => Ok(None),
+                };
+                let _ = reply.send(reopened);
+            }
+            Command::Flush(done) => {
+                let _ = done.send(());
+            }
+        }
+    }
+}
+
+fn send(command: Command) {
+    // the writer thread only ends with the process
+    let _ = writer().send(command);
+}
+
+// Send log lines to `path` from now on. The file is opened right away so a bad path is
+// reported to the caller.
+pub fn log_to(path: impl Into<PathBuf>) -> io::Result<()> {
+    send(Command::LogTo(LogFile::open(path)?));
+    Ok(())
+}
+
+// Reopen the log file, the path it was reopened at or None when logging to stdout
+pub async fn reopen() -> io::Result<Option<PathBuf>> {
+    let (tx, rx) = oneshot::channel();
+    send(Command::Reopen(tx));
+    rx.await
+        .unwrap_or_else(|_| Err(io::Error::other("log writer stopped")))
+}
+
+pub fn write(line: &str) {
+    send(Command::Line {
+        line: line.to_string(),
+        error: false,
+    });
+}
+
+// Like `write`, but goes to stderr when there is no log file
+pub fn error(line: &str) {
+    send(Command::Line {
+        line: line.to_string(),
+        error: true,
+    });
+}
+
+// Block until every line logged so far is written, for use right before the process exits
+pub fn flush() {
+    let (tx, rx) = mpsc::channel();
+    send(Command::Flush(tx));
+    let _ = rx.recv();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_reopen_after_rotation() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("access.log");
+        let rotated = directory.path().join("access.log.1");
+
+        let mut log_file = LogFile::open(&path).unwrap();
+        log_file.write_line("first").unwrap();
+        fs::rename(&path, &rotated).unwrap();
+        // still written to the renamed file until reopened
+        log_file.write_line("second").unwrap();
+        log_file.reopen().unwrap();
+        log_file.write_line("third").unwrap();
+
+        assert_eq!(fs::read_to_string(&rotated).unwrap(), "first\nsecond\n");
+        assert_eq!(fs::read_to_string(&path).unwrap(), "third\n");
+
+        // an existing file is appended to
+        let mut log_file = LogFile::open(&path).unwrap();
+        log_file.write_line("fourth").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "third\nfourth\n");
+    }
+
+    #[tokio::test]
+    async fn test_writer_follows_commands_in_order() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("server.log");
+        let rotated = directory.path().join("server.log.1");
+
+        let (commands, rx) = mpsc::channel();
+        let writer = thread::spawn(move || run_writer(rx));
+        let line = |line: &str, error| Command::Line {
+            line: line.to_string(),
+            error,
+        };
+
+        let (reply, reopened) = oneshot::channel();
+        commands.send(Command::Reopen(reply)).unwrap();
+        assert_eq!(reopened.await.unwrap().unwrap(), None);
+
+        commands
+            .send(Command::LogTo(LogFile::open(&path).unwrap()))
+            .unwrap();
+        commands.send(line("listening", false)).unwrap();
+        commands.send(line("failed", true)).unwrap();
+        let (done, flushed) = mpsc::channel();
+        commands.send(Command::Flush(done)).unwrap();
+        flushed.recv().unwrap();
+        fs::rename(&path, &rotated).unwrap();
+
+        let (reply, reopened) = oneshot::channel();
+        commands.send(Command::Reopen(reply)).unwrap();
+        assert_eq!(reopened.await.unwrap().unwrap(), Some(path.clone()));
+        commands.send(line("rotated", false)).unwrap();
+        drop(commands);
+        writer.join().unwrap();
+
+        assert_eq!(fs::read_to_string(&rotated).unwrap(), "listening\nfailed\n");
+        assert_eq!(fs::read_to_string(&path).unwrap(), "rotated\n");
+    }
+}
@@ -1,40 +1,55 @@
-use std::io::{self};
-use std::sync::Arc;
+use rust_http_server::server::{EXIT_CONFIG_ERROR, EXIT_IO_ERROR};
+use rust_http_server::{log, shutdown, Server, Settings, ShutdownSignal};
+use std::fmt::Display;
+use std::path::PathBuf;
 use tokio::sync::mpsc;
 
 mod cli;
-mod config;
-mod connection;
-mod file;
-mod http;
-mod request;
-mod response;
-mod server;
-mod shutdown;
-
-use config::Settings;
-use http::{HTTPBody, HTTPStatus};
-use server::Server;
-use shutdown::ShutdownSignal;
+mod routes;
 
 #[tokio::main]
-async fn main() -> io::Result<()> {
-    // load application configuration and create Arc so it can be shared safely amongst threads
-    let settings: Arc<Settings> = Arc::new(
-        Settings::load()
-            .await
-            .expect("Failed to load configuration!"),
-    );
+async fn main() {
+    let settings = match Settings::load().await {
+        Ok(settings) => settings,
+        Err(e) => exit_on_startup_error("Failed to load configuration", e, EXIT_CONFIG_ERROR),
+    };
+    if let Some(path) = &settings.log_file {
+        if let Err(e) = log::log_to(path) {
+            exit_on_startup_error("Failed to open the log file", e, EXIT_IO_ERROR);
+        }
+    }
 
     // open a channel for main thread to listen for shutdown signal
     let (tx, rx) = mpsc::channel::<ShutdownSignal>(1);
     shutdown::handle_shutdown_signals(tx).await;
 
-    let mut server = Server::new(settings, rx).await?;
-    // Run the server and handle its exit
-    if let Some(exit_code) = server.run().await {
-        std::process::exit(exit_code);
+    let files = cli::get_cli_arg_by_name("--directory").map(|directory| routes::FileStore {
+        directory: PathBuf::from(directory),
+        directory_listing: cli::has_cli_flag("--directory-listing"),
+        create_directories: cli::has_cli_flag("--create-directories"),
+        writers: Default::default(),
+    });
+
+    let server = Server::builder()
+        .settings(settings)
+        .router(routes::router(files))
+        .signals(rx)
+        .bind()
+        .await;
+    let mut server = match server {
+        Ok(server) => server,
+        Err(e) => exit_on_startup_error("Failed to listen", e, EXIT_IO_ERROR),
+    };
+    // Run the server and exit with its code, non-zero when it stopped on an error
+    let exit = server.run().await;
+    log::flush();
+    if !exit.is_success() {
+        std::process::exit(exit.code);
     }
+}
 
-    Ok(())
+fn exit_on_startup_error(message: &str, error: impl Display, code: i32) -> ! {
+    log::error(&format!("{}: {}", message, error));
+    log::flush();
+    std::process::exit(code);
 }
@@ -0,0 +1,646 @@
+use crate::http::{is_tchar, HTTPStatus, HeaderMap, Method, Version};
+use crate::request::RequestHeaders;
+use crate::uri::Uri;
+
+use nom::bytes::streaming::{tag, take_while, take_while1};
+use nom::character::streaming::{char, crlf, hex_digit1, satisfy};
+use nom::combinator::recognize;
+use nom::sequence::tuple;
+use nom::IResult;
+use thiserror::Error;
+
+const DEFAULT_MAX_REQUEST_LINE: usize = 8 * 1024;
+const DEFAULT_MAX_HEADER_BYTES: usize = 64 * 1024;
+const DEFAULT_MAX_HEADERS: usize = 100;
+const DEFAULT_MAX_BODY_SIZE: usize = 10 * 1024 * 1024;
+// empty lines tolerated before a request line, e.g. left over after a previous body
+const MAX_LEADING_EMPTY_LINES: usize = 2;
+// a chunk size never needs more hex digits than a usize holds, leading zeros included
+const MAX_CHUNK_SIZE_DIGITS: usize = 16;
+// chunk-size line including extensions, longer lines are rejected before they are buffered
+const MAX_CHUNK_LINE: usize = 4 * 1024;
+
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum ParseError {
+    #[error("invalid request method")]
+    InvalidMethod,
+    #[error("invalid request target")]
+    InvalidTarget,
+    #[error("invalid HTTP version")]
+    InvalidVersion,
+    #[error("unsupported HTTP version")]
+    UnsupportedVersion,
+    #[error("malformed request line")]
+    InvalidRequestLine,
+    #[error("malformed header field")]
+    InvalidHeader,
+    #[error("obsolete line folding in header field")]
+    ObsoleteLineFolding,
+    #[error("request line too long")]
+    RequestLineTooLong,
+    #[error("header section too large")]
+    HeadersTooLarge,
+    #[error("too many header fields")]
+    TooManyHeaders,
+    #[error("request body too large")]
+    BodyTooLarge,
+    #[error("invalid Content-Length")]
+    InvalidContentLength,
+    #[error("both Content-Length and Transfer-Encoding present")]
+    ConflictingLength,
+    #[error("invalid Transfer-Encoding")]
+    InvalidTransferEncoding,
+    #[error("unsupported transfer coding")]
+    UnsupportedTransferCoding,
+    #[error("malformed chunk")]
+    InvalidChunk,
+    #[error("request is incomplete")]
+    Incomplete,
+}
+
+impl ParseError {
+    // Status code the server answers with when a request fails to parse
+    pub fn status(&self) -> HTTPStatus {
+        match self {
+            ParseError::RequestLineTooLong => HTTPStatus::UriTooLong,
+            ParseError::HeadersTooLarge | ParseError::TooManyHeaders => {
+                HTTPStatus::RequestHeaderFieldsTooLarge
+            }
+            ParseError::BodyTooLarge => HTTPStatus::ContentTooLarge,
+            ParseError::UnsupportedVersion => HTTPStatus::HttpVersionNotSupported,
+            ParseError::UnsupportedTransferCoding => HTTPStatus::NotImplemented,
+            _ => HTTPStatus::BadRequest,
+        }
+    }
+}
+
+// Bounds on the size of a request, checked while it is read so a client cannot make the server
+// buffer more than this
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limits {
+    pub max_request_line: usize,
+    // header section without the request line, also applies to trailers
+    pub max_header_bytes: usize,
+    pub max_headers: usize,
+    // decoded body, for chunked requests the sum of all chunks
+    pub max_body_size: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            max_request_line: DEFAULT_MAX_REQUEST_LINE,
+            max_header_bytes: DEFAULT_MAX_HEADER_BYTES,
+            max_headers: DEFAULT_MAX_HEADERS,
+            max_body_size: DEFAULT_MAX_BODY_SIZE,
+        }
+    }
+}
+
+type ParseResult<'a, O> = IResult<&'a [u8], O, ParseError>;
+
+type NomError<'a> = nom::error::Error<&'a [u8]>;
+
+// Replace whatever error nom produced with our own, keeping `Incomplete` intact
+fn fail<'a>(error: ParseError) -> impl Fn(nom::Err<NomError<'a>>) -> nom::Err<ParseError> {
+    move |e| e.map(|_| error.clone())
+}
+
+// request-target is any run of visible ASCII characters, its structure is checked later
+fn is_target_char(c: u8) -> bool {
+    c.is_ascii_graphic()
+}
+
+// field-value may contain visible characters, spaces, tabs and obs-text
+fn is_field_char(c: u8) -> bool {
+    c == b'\t' || c == b' ' || c.is_ascii_graphic() || c >= 0x80
+}
+
+fn is_ows(c: u8) -> bool {
+    c == b' ' || c == b'\t'
+}
+
+fn http_version(input: &[u8]) -> ParseResult<'_, Version> {
+    let (input, version) = recognize(tuple((
+        tag("HTTP/"),
+        satisfy(|c| c.is_ascii_digit()),
+        char('.'),
+        satisfy(|c| c.is_ascii_digit()),
+    )))(input)
+    .map_err(fail(ParseError::InvalidVersion))?;
+
+    let version = std::str::from_utf8(version)
+        .ok()
+        .and_then(Version::parse)
+        .ok_or(nom::Err::Error(ParseError::UnsupportedVersion))?;
+    Ok((input, version))
+}
+
+// request-line = method SP request-target SP HTTP-version CRLF
+fn request_line(input: &[u8]) -> ParseResult<'_, (Method, Uri, Version)> {
+    let (input, method) = take_while1(is_tchar)(input).map_err(fail(ParseError::InvalidMethod))?;
+    let (input, _) = char(' ')(input).map_err(fail(ParseError::InvalidMethod))?;
+    let (input, target) =
+        take_while1(is_target_char)(input).map_err(fail(ParseError::InvalidTarget))?;
+    let (input, _) = char(' ')(input).map_err(fail(ParseError::InvalidRequestLine))?;
+    let (input, version) = http_version(input)?;
+    let (input, _) = crlf(input).map_err(fail(ParseError::InvalidRequestLine))?;
+
+    // both slices only hold ASCII so the conversions cannot lose anything
+    let method = Method::from_token(&String::from_utf8_lossy(method))
+        .ok_or(nom::Err::Error(ParseError::InvalidMethod))?;
+    let uri = Uri::parse(&String::from_utf8_lossy(target)).map_err(nom::Err::Error)?;
+    // the asterisk stands for the whole server and only makes sense for OPTIONS
+    if uri.is_asterisk() && method != Method::Options {
+        return Err(nom::Err::Error(ParseError::InvalidTarget));
+    }
+    Ok((input, (method, uri, version)))
+}
+
+// field-line = field-name ":" OWS field-value OWS CRLF
+fn header_field(input: &[u8]) -> ParseResult<'_, (String, String)> {
+    let (input, name) = take_while1(is_tchar)(input).map_err(fail(ParseError::InvalidHeader))?;
+    // whitespace between the field name and colon is not allowed (RFC 9112 section 5.1)
+    let (input, _) = char(':')(input).map_err(fail(ParseError::InvalidHeader))?;
+    let (input, _) = take_while(is_ows)(input).map_err(fail(ParseError::InvalidHeader))?;
+    let (input, value) =
+        take_while(is_field_char)(input).map_err(fail(ParseError::InvalidHeader))?;
+    let (input, _) = crlf(input).map_err(fail(ParseError::InvalidHeader))?;
+
+    let value_end = value.iter().rposition(|c| !is_ows(*c)).map_or(0, |i| i + 1);
+    Ok((
+        input,
+        (
+            String::from_utf8_lossy(name).into_owned(),
+            String::from_utf8_lossy(&value[..value_end]).into_owned(),
+        ),
+    ))
+}
+
+// Header fields up to and including the empty line that ends the section.
+// Shared by the request head and the trailer section of chunked bodies.
+fn field_section<'a>(mut input: &'a [u8], limits: &Limits) -> ParseResult<'a, HeaderMap> {
+    let mut headers = HeaderMap::new();
+
+    loop {
+        match crlf::<_, NomError>(input) {
+            Ok((rest, _)) => return Ok((rest, headers)),
+            Err(nom::Err::Incomplete(needed)) => return Err(nom::Err::Incomplete(needed)),
+            Err(_) => {}
+        }
+
+        // a field line starting with whitespace is either obs-fold or whitespace after the
+        // request line, both are rejected rather than unfolded (RFC 9112 sections 2.2 and 5.2)
+        if input.first().is_some_and(|c| is_ows(*c)) {
+            let error = if headers.is_empty() {
+                ParseError::InvalidHeader
+            } else {
+                ParseError::ObsoleteLineFolding
+            };
+            return Err(nom::Err::Error(error));
+        }
+
+        let (rest, (name, value)) = header_field(input)?;
+        if headers.len() >= limits.max_headers {
+            return Err(nom::Err::Error(ParseError::TooManyHeaders));
+        }
+        headers.append(&name, &value);
+        input = rest;
+    }
+}
+
+fn request_head<'a>(input: &'a [u8], limits: &Limits) -> ParseResult<'a, RequestHeaders> {
+    let (input, (method, uri, version)) = request_line(input)?;
+    let (input, headers) = field_section(input, limits)?;
+    Ok((
+        input,
+        RequestHeaders {
+            method,
+            uri,
+            version,
+            headers,
+        },
+    ))
+}
+
+// chunk-ext = *( BWS ";" BWS ext-name [ BWS "=" BWS ext-val ] ), ext-val = token / quoted-string
+fn chunk_extensions(mut input: &[u8]) -> ParseResult<'_, ()> {
+    loop {
+        let (rest, _) = take_while(is_ows)(input).map_err(fail(ParseError::InvalidChunk))?;
+        if rest.first() != Some(&b';') {
+            return Ok((input, ()));
+        }
+        let (rest, _) = take_while(is_ows)(&rest[1..]).map_err(fail(ParseError::InvalidChunk))?;
+        let (rest, _) = take_while1(is_tchar)(rest).map_err(fail(ParseError::InvalidChunk))?;
+        let (rest, _) = take_while(is_ows)(rest).map_err(fail(ParseError::InvalidChunk))?;
+        if rest.first() != Some(&b'=') {
+            input = rest;
+            continue;
+        }
+        let (rest, _) = take_while(is_ows)(&rest[1..]).map_err(fail(ParseError::InvalidChunk))?;
+        let (rest, _) = if rest.first() == Some(&b'"') {
+            quoted_string(rest)?
+        } else {
+            take_while1(is_tchar)(rest).map_err(fail(ParseError::InvalidChunk))?
+        };
+        input = rest;
+    }
+}
+
+// quoted-string = DQUOTE *( qdtext / quoted-pair ) DQUOTE
+fn quoted_string(input: &[u8]) -> ParseResult<'_, &[u8]> {
+    let mut escaped = false;
+    for (i, c) in input.iter().enumerate().skip(1) {
+        match c {
+            b'\r' | b'\n' => return Err(nom::Err::Error(ParseError::InvalidChunk)),
+            _ if escaped => escaped = false,
+            b'\\' => escaped = true,
+            b'"' => return Ok((&input[i + 1..], &input[..i + 1])),
+            _ => {}
+        }
+    }
+    Err(nom::Err::Incomplete(nom::Needed::new(1)))
+}
+
+// chunk-size [ chunk-ext ] CRLF
+fn chunk_header(input: &[u8]) -> ParseResult<'_, usize> {
+    let digits = input.iter().take_while(|c| c.is_ascii_hexdigit()).count();
+    if digits > MAX_CHUNK_SIZE_DIGITS {
+        return Err(nom::Err::Error(ParseError::InvalidChunk));
+    }
+    let (input, size) = hex_digit1(input).map_err(fail(ParseError::InvalidChunk))?;
+    let (input, _) = chunk_extensions(input)?;
+    let (input, _) = crlf(input).map_err(fail(ParseError::InvalidChunk))?;
+
+    // hex digits are ASCII, an overflowing size is rejected rather than wrapped
+    let size = usize::from_str_radix(&String::from_utf8_lossy(size), 16)
+        .map_err(|_| nom::Err::Error(ParseError::InvalidChunk))?;
+    Ok((input, size))
+}
+
+// Parse a chunk-size line with optional extensions from the start of `buf`.
+// Returns the chunk size and the length of the line, or `Ok(None)` when more bytes are needed.
+// Lines longer than `MAX_CHUNK_LINE` are rejected whether they are complete or not.
+pub fn parse_chunk_header(buf: &[u8]) -> Result<Option<(usize, usize)>, ParseError> {
+    match chunk_header(buf) {
+        Ok((rest, size)) => {
+            let line_length = buf.len() - rest.len();
+            if line_length > MAX_CHUNK_LINE {
+                return Err(ParseError::InvalidChunk);
+            }
+            Ok(Some((size, line_length)))
+        }
+        // everything buffered so far belongs to the unfinished line
+        Err(nom::Err::Incomplete(_)) if buf.len() > MAX_CHUNK_LINE => Err(ParseError::InvalidChunk),
+        Err(nom::Err::Incomplete(_)) => Ok(None),
+        Err(nom::Err::Error(e)) | Err(nom::Err::Failure(e)) => Err(e),
+    }
+}
+
+// Parse the trailer section that follows the last chunk, including its terminating empty line.
+pub fn parse_trailers(
+    buf: &[u8],
+    limits: &Limits,
+) -> Result<Option<(HeaderMap, usize)>, ParseError> {
+    match field_section(buf, limits) {
+        Ok((rest, trailers)) => {
+            let consumed = buf.len() - rest.len();
+            if consumed > limits.max_header_bytes {
+                return Err(ParseError::HeadersTooLarge);
+            }
+            Ok(Some((trailers, consumed)))
+        }
+        Err(nom::Err::Incomplete(_)) => {
+            if buf.len() > limits.max_header_bytes {
+                return Err(ParseError::HeadersTooLarge);
+            }
+            Ok(None)
+        }
+        Err(nom::Err::Error(e)) | Err(nom::Err::Failure(e)) => Err(e),
+    }
+}
+
+// Parse the request line and header section from the start of `buf`.
+// Returns `Ok(None)` when more bytes are needed, in which case the caller reads more from the
+// socket and calls again with the extended buffer. On success the number of bytes consumed is
+// returned alongside the headers, anything after that belongs to the body or the next request.
+pub fn parse_head(
+    buf: &[u8],
+    limits: &Limits,
+) -> Result<Option<(RequestHeaders, usize)>, ParseError> {
+    // a few empty lines before the request line are ignored (RFC 9112 section 2.2), more are
+    // rejected so they cannot be streamed without ever counting against a limit
+    let empty_lines = buf.chunks(2).take_while(|chunk| *chunk == b"\r\n").count();
+    if empty_lines > MAX_LEADING_EMPTY_LINES {
+        return Err(ParseError::InvalidRequestLine);
+    }
+    let input = &buf[empty_lines * 2..];
+    // the CR of another empty line, or of garbage that is rejected once its LF is missing
+    if input == b"\r" {
+        return Ok(None);
+    }
+
+    let request_line_end = input.windows(2).position(|w| w == b"\r\n");
+    match request_line_end {
+        Some(end) if end > limits.max_request_line => return Err(ParseError::RequestLineTooLong),
+        None if input.len() > limits.max_request_line => {
+            return Err(ParseError::RequestLineTooLong)
+        }
+        _ => {}
+    }
+
+    match request_head(input, limits) {
+        Ok((rest, headers)) => {
+            let consumed = buf.len() - rest.len();
+            let header_bytes = input.len() - rest.len() - request_line_end.unwrap_or(0);
+            if header_bytes > limits.max_header_bytes {
+                return Err(ParseError::HeadersTooLarge);
+            }
+            Ok(Some((headers, consumed)))
+        }
+        Err(nom::Err::Incomplete(_)) => {
+            let header_bytes = input.len() - request_line_end.unwrap_or(0);
+            if header_bytes > limits.max_header_bytes {
+                return Err(ParseError::HeadersTooLarge);
+            }
+            Ok(None)
+        }
+        Err(nom::Err::Error(e)) | Err(nom::Err::Failure(e)) => Err(e),
+    }
+}
+
+// Version of the request line at the start of `buf`, once that line is complete and valid, so a
+// request rejected for its headers can still be answered in its own version
+pub fn request_version(buf: &[u8]) -> Option<Version> {
+    let empty_lines = buf.chunks(2).take_while(|chunk| *chunk == b"\r\n").count();
+    request_line(&buf[empty_lines * 2..])
+        .ok()
+        .map(|(_, (_, _, version))| version)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(input: &str) -> Result<Option<(RequestHeaders, usize)>, ParseError> {
+        parse_head(input.as_bytes(), &Limits::default())
+    }
+
+    #[test]
+    fn test_parse_complete_head() {
+        let input = "GET /index.html HTTP/1.1\r\nHost: localhost\r\nAccept:*/*  \r\n\r\nbody";
+        let (headers, consumed) = parse(input).unwrap().unwrap();
+        assert_eq!(headers.method, Method::Get);
+        assert_eq!(headers.path(), "/index.html");
+        assert_eq!(headers.version, Version::Http11);
+        assert_eq!(headers.host(), Some("localhost"));
+        assert_eq!(headers.accept(), Some("*/*"));
+        assert_eq!(&input[consumed..], "body");
+    }
+
+    #[test]
+    fn test_parse_resumes_on_partial_input() {
+        let input = "POST /submit HTTP/1.0\r\nContent-Length: 4\r\n\r\n";
+        // every strict prefix needs more bytes and must not be reported as an error
+        for end in 0..input.len() {
+            assert_eq!(
+                parse(&input[..end]).map(|r| r.is_none()),
+                Ok(true),
+                "{}",
+                end
+            );
+        }
+        let (headers, consumed) = parse(input).unwrap().unwrap();
+        assert_eq!(headers.version, Version::Http10);
+        assert_eq!(headers.content_length(), Some(4));
+        assert_eq!(consumed, input.len());
+    }
+
+    #[test]
+    fn test_parse_skips_leading_empty_lines() {
+        let (headers, _) = parse("\r\n\r\nGET / HTTP/1.1\r\n\r\n").unwrap().unwrap();
+        assert_eq!(headers.path(), "/");
+        assert!(matches!(parse("\r"), Ok(None)));
+        assert!(matches!(parse("\r\n\r"), Ok(None)));
+
+        // streaming empty lines cannot grow the buffer past the limits
+        let flood = "\r\n".repeat(MAX_LEADING_EMPTY_LINES + 1);
+        assert_eq!(parse(&flood).err(), Some(ParseError::InvalidRequestLine));
+        let flood = "\r\n".repeat(10_000) + "GET / HTTP/1.1\r\n\r\n";
+        assert_eq!(parse(&flood).err(), Some(ParseError::InvalidRequestLine));
+    }
+
+    #[test]
+    fn test_parse_rejects_malformed_request_line() {
+        assert_eq!(
+            parse("INVALID REQUEST\r\n").err(),
+            Some(ParseError::InvalidRequestLine)
+        );
+        assert_eq!(
+            parse("G(ET / HTTP/1.1\r\n\r\n").err(),
+            Some(ParseError::InvalidMethod)
+        );
+        assert_eq!(
+            parse("GET  / HTTP/1.1\r\n\r\n").err(),
+            Some(ParseError::InvalidTarget)
+        );
+        assert_eq!(
+            parse("GET / HTTQ/1.1\r\n\r\n").err(),
+            Some(ParseError::InvalidVersion)
+        );
+        assert_eq!(
+            parse("GET / HTTP/1.1 \r\n\r\n").err(),
+            Some(ParseError::InvalidRequestLine)
+        );
+        assert_eq!(
+            parse("GET / HTTP/2.0\r\n\r\n").err(),
+            Some(ParseError::UnsupportedVersion)
+        );
+    }
+
+    #[test]
+    fn test_request_version() {
+        assert_eq!(
+            request_version(b"\r\nGET / HTTP/1.0\r\nBad Header\r\n"),
+            Some(Version::Http10)
+        );
+        assert_eq!(
+            request_version(b"GET / HTTP/1.1\r\n"),
+            Some(Version::Http11)
+        );
+        // not known before the request line is complete
+        assert_eq!(request_version(b"GET / HTTP/1.0"), None);
+        assert_eq!(request_version(b"GET / HTTP/2.0\r\n"), None);
+        assert_eq!(request_version(b"GET"), None);
+    }
+
+    #[test]
+    fn test_parse_request_targets() {
+        let input = "GET http://example.com/a%20b?x=1 HTTP/1.1\r\nHost: other\r\n\r\n";
+        let (headers, _) = parse(input).unwrap().unwrap();
+        assert_eq!(headers.path(), "/a%20b");
+        assert_eq!(headers.uri.segments(), ["a b"]);
+        assert_eq!(headers.query("x"), Some("1"));
+        // the authority of an absolute-form target wins over the Host header
+        assert_eq!(headers.host(), Some("example.com"));
+
+        let (headers, _) = parse("OPTIONS * HTTP/1.1\r\n\r\n").unwrap().unwrap();
+        assert!(headers.uri.is_asterisk());
+
+        for input in [
+            "GET * HTTP/1.1\r\n\r\n",
+            "GET a.txt HTTP/1.1\r\n\r\n",
+            "GET /a%zz HTTP/1.1\r\n\r\n",
+            "CONNECT example.com:443 HTTP/1.1\r\n\r\n",
+        ] {
+            assert_eq!(
+                parse(input).err(),
+                Some(ParseError::InvalidTarget),
+                "{}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_rejects_malformed_headers() {
+        assert_eq!(
+            parse("GET / HTTP/1.1\r\nHost : localhost\r\n\r\n").err(),
+            Some(ParseError::InvalidHeader)
+        );
+        assert_eq!(
+            parse("GET / HTTP/1.1\r\nNo colon here\r\n\r\n").err(),
+            Some(ParseError::InvalidHeader)
+        );
+        assert_eq!(
+            parse("GET / HTTP/1.1\r\n Host: localhost\r\n\r\n").err(),
+            Some(ParseError::InvalidHeader)
+        );
+        assert_eq!(
+            parse("GET / HTTP/1.1\r\nX-Long: a\r\n b\r\n\r\n").err(),
+            Some(ParseError::ObsoleteLineFolding)
+        );
+    }
+
+    #[test]
+    fn test_parse_enforces_limits() {
+        let limits = Limits {
+            max_request_line: 32,
+            max_header_bytes: 64,
+            max_headers: 2,
+            ..Limits::default()
+        };
+
+        let long_line = format!("GET /{} HTTP/1.1\r\n\r\n", "a".repeat(64));
+        assert_eq!(
+            parse_head(long_line.as_bytes(), &limits).err(),
+            Some(ParseError::RequestLineTooLong)
+        );
+        // an unterminated request line is rejected as soon as it exceeds the limit
+        assert_eq!(
+            parse_head(&long_line.as_bytes()[..40], &limits).err(),
+            Some(ParseError::RequestLineTooLong)
+        );
+
+        let large_headers = format!("GET / HTTP/1.1\r\nX-Big: {}\r\n\r\n", "a".repeat(64));
+        assert_eq!(
+            parse_head(large_headers.as_bytes(), &limits).err(),
+            Some(ParseError::HeadersTooLarge)
+        );
+
+        let many_headers = "GET / HTTP/1.1\r\nA: 1\r\nB: 2\r\nC: 3\r\n\r\n";
+        assert_eq!(
+            parse_head(many_headers.as_bytes(), &limits).err(),
+            Some(ParseError::TooManyHeaders)
+        );
+    }
+
+    #[test]
+    fn test_parse_chunk_header() {
+        assert_eq!(parse_chunk_header(b"1a\r\ndata"), Ok(Some((26, 4))));
+        assert_eq!(parse_chunk_header(b"0\r\n"), Ok(Some((0, 3))));
+        assert_eq!(parse_chunk_header(b"A;name=value\r\n"), Ok(Some((10, 14))));
+        assert_eq!(
+            parse_chunk_header(b"5 ; a ; b=\"x;\\\"y\"\r\n"),
+            Ok(Some((5, 19)))
+        );
+        assert_eq!(parse_chunk_header(b"1f"), Ok(None));
+        assert_eq!(parse_chunk_header(b"1f;ext"), Ok(None));
+        assert_eq!(parse_chunk_header(b"1f\r"), Ok(None));
+        assert_eq!(
+            parse_chunk_header(b"xyz\r\n"),
+            Err(ParseError::InvalidChunk)
+        );
+        assert_eq!(
+            parse_chunk_header(b"5 x\r\n"),
+            Err(ParseError::InvalidChunk)
+        );
+        assert_eq!(
+            parse_chunk_header(b"fffffffffffffffffffff\r\n"),
+            Err(ParseError::InvalidChunk)
+        );
+    }
+
+    #[test]
+    fn test_chunk_header_length_is_bounded() {
+        // leading zeros cannot be streamed forever
+        let zeros = "0".repeat(MAX_CHUNK_SIZE_DIGITS);
+        assert_eq!(parse_chunk_header(zeros.as_bytes()), Ok(None));
+        let zeros = "0".repeat(MAX_CHUNK_SIZE_DIGITS + 1);
+        assert_eq!(
+            parse_chunk_header(zeros.as_bytes()),
+            Err(ParseError::InvalidChunk)
+        );
+
+        // neither can extensions, complete or not
+        let extensions = format!("5;{}", "a".repeat(MAX_CHUNK_LINE));
+        assert_eq!(
+            parse_chunk_header(extensions.as_bytes()),
+            Err(ParseError::InvalidChunk)
+        );
+        let extensions = format!("5;{}\r\n", "a".repeat(MAX_CHUNK_LINE));
+        assert_eq!(
+            parse_chunk_header(extensions.as_bytes()),
+            Err(ParseError::InvalidChunk)
+        );
+        let extensions = format!("5;{}\r\n", "a".repeat(MAX_CHUNK_LINE - 10));
+        assert!(parse_chunk_header(extensions.as_bytes()).unwrap().is_some());
+    }
+
+    #[test]
+    fn test_parse_trailers() {
+        let limits = Limits::default();
+        let (trailers, consumed) = parse_trailers(b"Expires: never\r\n\r\nnext", &limits)
+            .unwrap()
+            .unwrap();
+        assert_eq!(trailers.get("expires"), Some("never"));
+        assert_eq!(consumed, 18);
+
+        let (trailers, consumed) = parse_trailers(b"\r\n", &limits).unwrap().unwrap();
+        assert!(trailers.is_empty());
+        assert_eq!(consumed, 2);
+
+        assert_eq!(parse_trailers(b"Expires: never\r\n", &limits), Ok(None));
+    }
+
+    #[test]
+    fn test_parse_error_status() {
+        assert_eq!(ParseError::InvalidHeader.status(), HTTPStatus::BadRequest);
+        assert_eq!(
+            ParseError::RequestLineTooLong.status(),
+            HTTPStatus::UriTooLong
+        );
+        assert_eq!(
+            ParseError::HeadersTooLarge.status(),
+            HTTPStatus::RequestHeaderFieldsTooLarge
+        );
+        assert_eq!(
+            ParseError::BodyTooLarge.status(),
+            HTTPStatus::ContentTooLarge
+        );
+        assert_eq!(
+            ParseError::UnsupportedVersion.status(),
+            HTTPStatus::HttpVersionNotSupported
+        );
+    }
+}
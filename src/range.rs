@@ -0,0 +1,368 @@
+use crate::conditional::{EntityTag, Validators};
+use crate::date::parse_http_date;
+use crate::http::Method;
+use crate::request::RequestHeaders;
+
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::time::UNIX_EPOCH;
+
+// Requests asking for more ranges than this are served in full instead
+const MAX_RANGES: usize = 64;
+
+// Inclusive span of bytes of a representation
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ByteRange {
+    pub start: u64,
+    pub end: u64,
+}
+
+impl ByteRange {
+    // Saturates for the one span whose length does not fit, 0 through u64::MAX
+    pub fn length(&self) -> u64 {
+        self.end.saturating_sub(self.start).saturating_add(1)
+    }
+
+    // Value of the Content-Range header for this span of a representation of `complete` bytes
+    pub fn content_range(&self, complete: u64) -> String {
+        format!("bytes {}-{}/{}", self.start, self.end, complete)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RangeRequest {
+    // no usable Range header, send the whole representation
+    Full,
+    // satisfiable ranges in ascending order, overlapping and adjacent ranges are merged
+    Partial(Vec<ByteRange>),
+    // none of the ranges overlap the representation, answer 416
+    Unsatisfiable,
+}
+
+// Parse a Range header for a representation of `length` bytes. Headers with another unit or
+// invalid syntax are ignored as RFC 9110 allows, so the client gets the full content.
+pub fn parse_range(value: &str, length: u64) -> RangeRequest {
+    let Some((unit, specs)) = value.trim().split_once('=') else {
+        return RangeRequest::Full;
+    };
+    if !unit.trim().eq_ignore_ascii_case("bytes") {
+        return RangeRequest::Full;
+    }
+
+    let specs: Vec<&str> = specs
+        .split(',')
+        .map(str::trim)
+        .filter(|spec| !spec.is_empty())
+        .collect();
+    if specs.is_empty() || specs.len() > MAX_RANGES {
+        return RangeRequest::Full;
+    }
+
+    let mut ranges = Vec::new();
+    for spec in specs {
+        let Some((first, last)) = spec.split_once('-') else {
+            return RangeRequest::Full;
+        };
+        let (first, last) = (first.trim(), last.trim());
+
+        if first.is_empty() {
+            // suffix range, the final `last` bytes
+            let Some(suffix) = parse_position(last) else {
+                return RangeRequest::Full;
+            };
+            if suffix > 0 && length > 0 {
+                ranges.push(ByteRange {
+                    start: length.saturating_sub(suffix),
+                    end: length - 1,
+                });
+            }
+            continue;
+        }
+
+        let Some(start) = parse_position(first) else {
+            return RangeRequest::Full;
+        };
+        let end = if last.is_empty() {
+            u64::MAX
+        } else {
+            match parse_position(last) {
+                Some(end) if end >= start => end,
+                _ => return RangeRequest::Full,
+            }
+        };
+        if start < length {
+            ranges.push(ByteRange {
+                start,
+                end: end.min(length - 1),
+            });
+        }
+    }
+
+    if ranges.is_empty() {
+        return RangeRequest::Unsatisfiable;
+    }
+    RangeRequest::Partial(coalesce(ranges))
+}
+
+fn parse_position(value: &str) -> Option<u64> {
+    if value.is_empty() || !value.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    value.parse().ok()
+}
+
+fn coalesce(mut ranges: Vec<ByteRange>) -> Vec<ByteRange> {
+    ranges.sort_by_key(|range| range.start);
+    let mut merged: Vec<ByteRange> = Vec::with_capacity(ranges.len());
+    for range in ranges {
+        match merged.last_mut() {
+            Some(last) if range.start <= last.end.saturating_add(1) => {
+                last.end = last.end.max(range.end);
+            }
+            _ => merged.push(range),
+        }
+    }
+    merged
+}
+
+// Parse a Content-Range header of the form `bytes first-last/complete`, where `complete` may be
+// `*` when the full length is unknown. Returns the range and the complete length if given.
+pub fn parse_content_range(value: &str) -> Option<(ByteRange, Option<u64>)> {
+    let (unit, rest) = value.trim().split_once(' ')?;
+    if !unit.eq_ignore_ascii_case("bytes") {
+        return None;
+    }
+    let (span, complete) = rest.trim().split_once('/')?;
+    let (first, last) = span.split_once('-')?;
+    let range = ByteRange {
+        start: parse_position(first)?,
+        end: parse_position(last)?,
+    };
+    let complete = match complete {
+        "*" => None,
+        complete => Some(parse_position(complete)?),
+    };
+    // a range ending at u64::MAX could never be written or held in a representation
+    if range.end < range.start
+        || range.end == u64::MAX
+        || complete.is_some_and(|complete| range.end >= complete)
+    {
+        return None;
+    }
+    Some((range, complete))
+}
+
+// If-Range makes a range request conditional on the representation being unchanged. It needs a
+// strong validator, an entity tag has to match strongly and a date has to equal Last-Modified.
+pub fn if_range_holds(request: &RequestHeaders, current: &Validators) -> bool {
+    let Some(value) = request.header("If-Range") else {
+        return true;
+    };
+    if let Some(tag) = EntityTag::parse(value) {
+        return current
+            .etag
+            .as_ref()
+            .is_some_and(|etag| etag.strong_eq(&tag));
+    }
+
+    let seconds = |time: std::time::SystemTime| {
+        time.duration_since(UNIX_EPOCH)
+            .ok()
+            .map(|duration| duration.as_secs())
+    };
+    match (parse_http_date(value), current.last_modified) {
+        (Some(date), Some(modified)) => seconds(date) == seconds(modified),
+        _ => false,
+    }
+}
+
+// Ranges to send for a GET of a representation of `length` bytes
+pub fn evaluate(request: &RequestHeaders, length: u64, current: &Validators) -> RangeRequest {
+    if !matches!(request.method, Method::Get | Method::Head) {
+        return RangeRequest::Full;
+    }
+    match request.header("Range") {
+        Some(value) if if_range_holds(request, current) => parse_range(value, length),
+        _ => RangeRequest::Full,
+    }
+}
+
+// Framing of a `multipart/byteranges` body, the content of each range goes between the part
+// headers and the next delimiter
+pub struct Multipart {
+    pub boundary: String,
+    content_type: String,
+    complete: u64,
+}
+
+impl Multipart {
+    pub fn new(content_type: &str, complete: u64) -> Self {
+        // unpredictable enough that it does not turn up in the content by accident
+        let mut hasher = RandomState::new().build_hasher();
+        hasher.write_u64(complete);
+        Multipart {
+            boundary: format!("{:016x}{:016x}", hasher.finish(), complete),
+            content_type: content_type.to_string(),
+            complete,
+        }
+    }
+
+    pub fn content_type(&self) -> String {
+        format!("multipart/byteranges; boundary={}", self.boundary)
+    }
+
+    pub fn part_head(&self, range: &ByteRange) -> String {
+        format!(
+            "\r\n--{}\r\nContent-Type: {}\r\nContent-Range: {}\r\n\r\n",
+            self.boundary,
+            self.content_type,
+            range.content_range(self.complete)
+        )
+    }
+
+    pub fn closing(&self) -> String {
+        format!("\r\n--{}--\r\n", self.boundary)
+    }
+
+    // Size of the whole body for the given ranges
+    pub fn body_length(&self, ranges: &[ByteRange]) -> u64 {
+        let parts: u64 = ranges
+            .iter()
+            .map(|range| self.part_head(range).len() as u64 + range.length())
+            .sum();
+        parts + self.closing().len() as u64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::request::parse_request_headers;
+    use std::time::Duration;
+
+    fn ranges(spans: &[(u64, u64)]) -> RangeRequest {
+        RangeRequest::Partial(
+            spans
+                .iter()
+                .map(|&(start, end)| ByteRange { start, end })
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn test_parse_range() {
+        assert_eq!(parse_range("bytes=0-99", 1000), ranges(&[(0, 99)]));
+        assert_eq!(parse_range("bytes=500-", 1000), ranges(&[(500, 999)]));
+        assert_eq!(parse_range("bytes=-200", 1000), ranges(&[(800, 999)]));
+        assert_eq!(parse_range("bytes=-2000", 1000), ranges(&[(0, 999)]));
+        assert_eq!(parse_range("bytes=900-2000", 1000), ranges(&[(900, 999)]));
+        assert_eq!(
+            parse_range("Bytes = 0-0 , -1", 1000),
+            ranges(&[(0, 0), (999, 999)])
+        );
+    }
+
+    #[test]
+    fn test_parse_range_coalesces() {
+        assert_eq!(
+            parse_range("bytes=500-600,0-10,11-20,550-700", 1000),
+            ranges(&[(0, 20), (500, 700)])
+        );
+    }
+
+    #[test]
+    fn test_parse_range_ignores_invalid_headers() {
+        for value in [
+            "items=0-1",
+            "bytes=",
+            "bytes=abc",
+            "bytes=5-1",
+            "bytes=1-2-3",
+            "bytes=-",
+            "bytes=+1-2",
+            "0-1",
+        ] {
+            assert_eq!(parse_range(value, 1000), RangeRequest::Full, "{}", value);
+        }
+        let too_many = format!("bytes={}", vec!["0-0"; MAX_RANGES + 1].join(","));
+        assert_eq!(parse_range(&too_many, 1000), RangeRequest::Full);
+    }
+
+    #[test]
+    fn test_parse_range_unsatisfiable() {
+        assert_eq!(
+            parse_range("bytes=1000-", 1000),
+            RangeRequest::Unsatisfiable
+        );
+        assert_eq!(parse_range("bytes=-0", 1000), RangeRequest::Unsatisfiable);
+        assert_eq!(parse_range("bytes=0-10", 0), RangeRequest::Unsatisfiable);
+        // one satisfiable range is enough
+        assert_eq!(parse_range("bytes=2000-3000,0-1", 1000), ranges(&[(0, 1)]));
+    }
+
+    #[test]
+    fn test_parse_content_range() {
+        let range = ByteRange { start: 5, end: 9 };
+        assert_eq!(parse_content_range("bytes 5-9/10"), Some((range, Some(10))));
+        assert_eq!(parse_content_range("bytes 5-9/*"), Some((range, None)));
+        assert_eq!(parse_content_range("bytes 5-9/9"), None);
+        assert_eq!(parse_content_range("bytes 9-5/*"), None);
+        assert_eq!(parse_content_range("bytes */10"), None);
+        assert_eq!(parse_content_range("items 5-9/10"), None);
+        assert_eq!(parse_content_range("bytes=5-9/10"), None);
+        assert_eq!(parse_content_range("bytes 0-18446744073709551615/*"), None);
+        let range = ByteRange {
+            start: 0,
+            end: u64::MAX,
+        };
+        assert_eq!(range.length(), u64::MAX);
+    }
+
+    #[tokio::test]
+    async fn test_if_range() {
+        let current = Validators {
+            etag: Some(EntityTag::strong("v1")),
+            last_modified: Some(UNIX_EPOCH + Duration::from_secs(784_111_777)),
+        };
+        let cases = [
+            ("", true),
+            ("If-Range: \"v1\"\r\n", true),
+            ("If-Range: \"v2\"\r\n", false),
+            ("If-Range: W/\"v1\"\r\n", false),
+            ("If-Range: Sun, 06 Nov 1994 08:49:37 GMT\r\n", true),
+            ("If-Range: Sun, 06 Nov 1994 08:49:38 GMT\r\n", false),
+            ("If-Range: garbage\r\n", false),
+        ];
+        for (headers, expected) in cases {
+            let raw = format!("GET / HTTP/1.1\r\nRange: bytes=0-1\r\n{}\r\n", headers);
+            let request = parse_request_headers(&raw).await.unwrap();
+            assert_eq!(if_range_holds(&request, &current), expected, "{}", headers);
+
+            let expected = if expected {
+                ranges(&[(0, 1)])
+            } else {
+                RangeRequest::Full
+            };
+            assert_eq!(evaluate(&request, 10, &current), expected);
+        }
+    }
+
+    #[test]
+    fn test_multipart_length() {
+        let multipart = Multipart::new("text/plain", 100);
+        let spans = [
+            ByteRange { start: 0, end: 9 },
+            ByteRange { start: 50, end: 59 },
+        ];
+        let mut body = String::new();
+        for range in &spans {
+            body.push_str(&multipart.part_head(range));
+            body.push_str(&"x".repeat(range.length() as usize));
+        }
+        body.push_str(&multipart.closing());
+
+        assert_eq!(multipart.body_length(&spans), body.len() as u64);
+        assert!(body.contains("\r\nContent-Range: bytes 50-59/100\r\n"));
+        assert!(body.ends_with(&format!("--{}--\r\n", multipart.boundary)));
+    }
+}
@@ -1,76 +1,466 @@
+use crate::http::{HeaderMap, Method, Version};
+use crate::parser::{self, Limits, ParseError};
+use crate::router::PathParams;
+use crate::uri::Uri;
+
+use bytes::{Buf, Bytes, BytesMut};
+use serde::de::DeserializeOwned;
 use std::io::{self, Error, ErrorKind};
-use tokio::io::AsyncReadExt;
-use tokio::net::TcpStream;
+use std::ops::Range;
+use std::str::Utf8Error;
+use std::time::Duration;
+use thiserror::Error;
+use tokio::io::{AsyncRead, AsyncReadExt};
+use tokio::time::{self, Instant};
+
+const READ_CHUNK_SIZE: usize = 1024;
+const DEFAULT_IDLE_TIMEOUT_SECS: u64 = 10;
+const DEFAULT_HEADER_TIMEOUT_SECS: u64 = 10;
+const DEFAULT_BODY_TIMEOUT_SECS: u64 = 10;
+const DEFAULT_MIN_BODY_RATE: usize = 1024;
 
 pub struct RequestHeaders {
-    pub method: String,
-    pub path: String,
-    pub user_agent: String,
-    pub content_length: Option<usize>,
+    pub method: Method,
+    pub uri: Uri,
+    pub version: Version,
+    pub headers: HeaderMap,
+}
+
+impl RequestHeaders {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name)
+    }
+
+    // Path of the request target, still percent-encoded and without the query
+    pub fn path(&self) -> &str {
+        self.uri.path()
+    }
+
+    // First value of a query string parameter
+    pub fn query(&self, name: &str) -> Option<&str> {
+        self.uri.query_params().get(name)
+    }
+
+    // An absolute-form target overrides the Host header (RFC 9112 section 3.2.2)
+    pub fn host(&self) -> Option<&str> {
+        self.uri.authority().or_else(|| self.header("Host"))
+    }
+
+    pub fn user_agent(&self) -> Option<&str> {
+        self.header("User-Agent")
+    }
+
+    pub fn accept(&self) -> Option<&str> {
+        self.header("Accept")
+    }
+
+    pub fn authorization(&self) -> Option<&str> {
+        self.header("Authorization")
+    }
+
+    pub fn content_type(&self) -> Option<&str> {
+        self.header("Content-Type")
+    }
+
+    pub fn content_length(&self) -> Option<usize> {
+        self.header("Content-Length")
+            .and_then(|value| value.parse().ok())
+    }
+
+    // Cookies may be split over several Cookie headers, each holding `name=value; name=value`
+    pub fn cookies(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.headers
+            .get_all("Cookie")
+            .flat_map(|value| value.split(';'))
+            .filter_map(|pair| pair.trim().split_once('='))
+    }
+
+    pub fn has_connection_option(&self, option: &str) -> bool {
+        self.headers
+            .get_all("Connection")
+            .flat_map(|value| value.split(','))
+            .any(|token| token.trim().eq_ignore_ascii_case(option))
+    }
+
+    // HTTP/1.1 connections persist unless the client asks to close them,
+    // HTTP/1.0 clients have to opt in with `Connection: keep-alive`
+    pub fn keep_alive(&self) -> bool {
+        match self.version {
+            Version::Http11 => !self.has_connection_option("close"),
+            Version::Http10 => self.has_connection_option("keep-alive"),
+        }
+    }
+
+    pub fn cookie(&self, name: &str) -> Option<&str> {
+        self.cookies()
+            .find(|(key, _)| *key == name)
+            .map(|(_, value)| value)
+    }
 }
 
 pub struct ParsedRequest {
     pub headers: RequestHeaders,
-    pub body: Option<String>,
-}
-
-pub async fn parse_request_headers(headers: &str) -> Result<RequestHeaders, Error> {
-    headers.split("\r\n").try_fold(
-        RequestHeaders {
-            method: String::new(),
-            path: String::new(),
-            user_agent: String::new(),
-            content_length: None,
-        },
-        |mut acc, line| {
-            match line.split_once(' ') {
-                Some(("GET", path)) | Some(("POST", path)) => {
-                    acc.method = line.split_whitespace().next().unwrap_or("").to_string();
-                    acc.path = path.split_whitespace().next().unwrap_or("").to_string();
-                }
-                Some((key, value)) if key == "User-Agent:" => {
-                    acc.user_agent = value.to_string();
-                }
-                Some((key, value)) if key == "Content-Length:" => {
-                    acc.content_length = value.parse().ok();
-                }
-                _ => {}
+    // raw body bytes, empty when the request carried no body
+    pub body: Bytes,
+    // trailer fields sent after a chunked body
+    pub trailers: HeaderMap,
+    // values captured from the route pattern, filled in by the router
+    pub params: PathParams,
+}
+
+impl ParsedRequest {
+    pub fn has_body(&self) -> bool {
+        !self.body.is_empty()
+    }
+
+    pub fn text(&self) -> Result<&str, Utf8Error> {
+        std::str::from_utf8(&self.body)
+    }
+
+    pub fn json<T: DeserializeOwned>(&self) -> serde_json::Result<T> {
+        serde_json::from_slice(&self.body)
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum RequestError {
+    #[error("failed to parse request: {0}")]
+    Parse(#[from] ParseError),
+    #[error("failed to read request: {0}")]
+    Io(#[from] io::Error),
+    // nothing of a request arrived in time
+    #[error("connection idle for too long")]
+    IdleTimeout,
+    // a request was started but not completed in time
+    #[error("request not received in time")]
+    Timeout,
+}
+
+// How long reading a request may take, phase by phase
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Timeouts {
+    // until the first byte of the request, on a fresh connection or between keep-alive requests
+    pub idle: Duration,
+    // from the first byte until the end of the header section
+    pub headers: Duration,
+    // for the body, extended by one second for every `min_body_rate` bytes received
+    pub body: Duration,
+    // bytes per second, 0 to give the body no more than `body`
+    pub min_body_rate: usize,
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Timeouts {
+            idle: Duration::from_secs(DEFAULT_IDLE_TIMEOUT_SECS),
+            headers: Duration::from_secs(DEFAULT_HEADER_TIMEOUT_SECS),
+            body: Duration::from_secs(DEFAULT_BODY_TIMEOUT_SECS),
+            min_body_rate: DEFAULT_MIN_BODY_RATE,
+        }
+    }
+}
+
+pub async fn parse_request_headers(headers: &str) -> Result<RequestHeaders, ParseError> {
+    match parser::parse_head(headers.as_bytes(), &Limits::default())? {
+        Some((parsed_headers, _)) => Ok(parsed_headers),
+        None => Err(ParseError::Incomplete),
+    }
+}
+
+// How the length of a request body is determined (RFC 9112 section 6.3)
+#[derive(Debug, PartialEq, Eq)]
+enum BodyLength {
+    Fixed(usize),
+    Chunked,
+}
+
+fn body_length(headers: &RequestHeaders) -> Result<BodyLength, ParseError> {
+    let has_content_length = headers.headers.contains("Content-Length");
+
+    if headers.headers.contains("Transfer-Encoding") {
+        // a message with both is a request smuggling vector and is rejected outright
+        if has_content_length {
+            return Err(ParseError::ConflictingLength);
+        }
+        let codings: Vec<String> = headers
+            .headers
+            .get_all("Transfer-Encoding")
+            .flat_map(|value| value.split(','))
+            .map(|coding| coding.trim().to_ascii_lowercase())
+            .collect();
+        return match codings.iter().map(String::as_str).collect::<Vec<_>>()[..] {
+            ["chunked"] => Ok(BodyLength::Chunked),
+            _ if codings.iter().any(|c| c != "chunked" && !c.is_empty()) => {
+                Err(ParseError::UnsupportedTransferCoding)
             }
-            Ok(acc)
-        },
-    )
+            _ => Err(ParseError::InvalidTransferEncoding),
+        };
+    }
+
+    if !has_content_length {
+        return Ok(BodyLength::Fixed(0));
+    }
+    // repeated Content-Length headers are only acceptable when they all agree
+    let mut lengths = headers
+        .headers
+        .get_all("Content-Length")
+        .map(|value| value.parse::<usize>().ok());
+    let first = lengths.next().flatten();
+    match first {
+        Some(length) if lengths.all(|other| other == Some(length)) => Ok(BodyLength::Fixed(length)),
+        _ => Err(ParseError::InvalidContentLength),
+    }
+}
+
+// Reads the body of a request, each read has to finish before the deadline. The client gets
+// `timeouts.body` to start with and more time for every byte of body data it sends, so a slow
+// but steady upload completes while a trickle of a few bytes does not keep the connection
+// forever. Chunk framing earns no time.
+struct BodyReader<'a, S> {
+    stream: &'a mut S,
+    timeouts: &'a Timeouts,
+    start: Instant,
+    received: usize,
 }
 
-pub async fn parse_stream(stream: &mut TcpStream) -> io::Result<ParsedRequest> {
-    let mut headers = String::new();
-    let mut header_buffer = [0; 1024];
+impl<S> BodyReader<'_, S>
+where
+    S: AsyncRead + Unpin,
+{
+    fn deadline(&self) -> Instant {
+        let earned = match self.timeouts.min_body_rate {
+            0 => Duration::ZERO,
+            rate => Duration::from_secs_f64(self.received as f64 / rate as f64),
+        };
+        self.start + self.timeouts.body + earned
+    }
 
-    while !headers.ends_with("\r\n\r\n") {
-        let bytes_read = stream.read(&mut header_buffer).await?;
-        if bytes_read == 0 {
-            return Err(Error::new(ErrorKind::BrokenPipe, "Connection closed"));
+    async fn read_more(&mut self, buffer: &mut BytesMut) -> Result<(), RequestError> {
+        buffer.reserve(READ_CHUNK_SIZE);
+        let read = time::timeout_at(self.deadline(), self.stream.read_buf(buffer))
+            .await
+            .map_err(|_| RequestError::Timeout)??;
+        if read == 0 {
+            return Err(Error::new(ErrorKind::BrokenPipe, "Connection closed").into());
         }
-        headers.push_str(&String::from_utf8_lossy(&header_buffer[..bytes_read]));
+        Ok(())
     }
 
-    let parsed_headers = parse_request_headers(&headers).await?;
-    let body_length = parsed_headers.content_length.unwrap_or(0);
-    let mut body_bytes = vec![0; body_length];
-    stream.read_exact(&mut body_bytes).await?;
+    // Read until `buffer` holds `end` bytes, the bytes in `data` are body data and earn time
+    async fn read_data(
+        &mut self,
+        buffer: &mut BytesMut,
+        data: Range<usize>,
+        end: usize,
+    ) -> Result<(), RequestError> {
+        while buffer.len() < end {
+            let before = buffer.len().max(data.start);
+            self.read_more(buffer).await?;
+            self.received += buffer.len().min(data.end).saturating_sub(before);
+        }
+        Ok(())
+    }
+}
 
-    let body_str =
-        String::from_utf8(body_bytes).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+async fn read_fixed_body<S>(
+    reader: &mut BodyReader<'_, S>,
+    buffer: &mut BytesMut,
+    length: usize,
+    limits: &Limits,
+) -> Result<Bytes, RequestError>
+where
+    S: AsyncRead + Unpin,
+{
+    // refused before reading anything, the client may be announcing far more than it sends
+    if length > limits.max_body_size {
+        return Err(ParseError::BodyTooLarge.into());
+    }
+    // part of the body may already have been read along with the headers
+    reader.read_data(buffer, 0..length, length).await?;
+    Ok(buffer.split_to(length).freeze())
+}
+
+async fn read_chunked_body<S>(
+    reader: &mut BodyReader<'_, S>,
+    buffer: &mut BytesMut,
+    limits: &Limits,
+) -> Result<(Bytes, HeaderMap), RequestError>
+where
+    S: AsyncRead + Unpin,
+{
+    let mut body = BytesMut::new();
+    // chunk lines and the CRLF after each chunk may add up to the data received so far plus
+    // `max_header_bytes`, so many tiny chunks cannot make the request arbitrarily long
+    let mut framing = 0;
+
+    loop {
+        let (size, line_length) = match parser::parse_chunk_header(buffer)? {
+            Some(header) => header,
+            None => {
+                reader.read_more(buffer).await?;
+                continue;
+            }
+        };
+        framing += line_length + 2;
+        if framing > body.len() + limits.max_header_bytes {
+            return Err(ParseError::BodyTooLarge.into());
+        }
+
+        if size == 0 {
+            buffer.advance(line_length);
+            let trailers = loop {
+                if let Some((trailers, consumed)) = parser::parse_trailers(buffer, limits)? {
+                    buffer.advance(consumed);
+                    break trailers;
+                }
+                reader.read_more(buffer).await?;
+            };
+            return Ok((body.freeze(), trailers));
+        }
+
+        if size > limits.max_body_size - body.len() {
+            return Err(ParseError::BodyTooLarge.into());
+        }
+        // chunk data is followed by its own CRLF
+        let chunk_end = line_length
+            .checked_add(size)
+            .and_then(|end| end.checked_add(2))
+            .ok_or(ParseError::InvalidChunk)?;
+        reader
+            .read_data(buffer, line_length..chunk_end - 2, chunk_end)
+            .await?;
+        if &buffer[chunk_end - 2..chunk_end] != b"\r\n" {
+            return Err(ParseError::InvalidChunk.into());
+        }
+        body.extend_from_slice(&buffer[line_length..chunk_end - 2]);
+        buffer.advance(chunk_end);
+    }
+}
+
+// Wait for the first bytes of the next request, false when the client closes the connection
+// instead. Nothing read is lost when this is cancelled, so it can race other events.
+pub async fn wait_for_request<S>(
+    stream: &mut S,
+    buffer: &mut BytesMut,
+    idle: Duration,
+) -> Result<bool, RequestError>
+where
+    S: AsyncRead + Unpin,
+{
+    buffer.reserve(READ_CHUNK_SIZE);
+    let read = time::timeout(idle, stream.read_buf(buffer))
+        .await
+        .map_err(|_| RequestError::IdleTimeout)??;
+    Ok(read > 0)
+}
+
+// Read the next request from a persistent connection. `buffer` holds bytes that were read from
+// the stream but not consumed yet, so pipelined requests that arrived together with an earlier
+// one are picked up from there. Returns `Ok(None)` when the client closes the connection
+// between requests. Requests exceeding `limits` fail as soon as that is known, requests that
+// are not received within `timeouts` fail with `IdleTimeout` or `Timeout`.
+pub async fn read_request<S>(
+    stream: &mut S,
+    buffer: &mut BytesMut,
+    limits: &Limits,
+    timeouts: &Timeouts,
+) -> Result<Option<ParsedRequest>, RequestError>
+where
+    S: AsyncRead + Unpin,
+{
+    match read_head(stream, buffer, limits, timeouts).await? {
+        Some(headers) => read_body(stream, buffer, headers, limits, timeouts)
+            .await
+            .map(Some),
+        None => Ok(None),
+    }
+}
+
+// First half of `read_request`, the request line and headers. A failed head is left in
+// `buffer` so the caller can still look at it.
+pub async fn read_head<S>(
+    stream: &mut S,
+    buffer: &mut BytesMut,
+    limits: &Limits,
+    timeouts: &Timeouts,
+) -> Result<Option<RequestHeaders>, RequestError>
+where
+    S: AsyncRead + Unpin,
+{
+    if buffer.is_empty() && !wait_for_request(stream, buffer, timeouts.idle).await? {
+        return Ok(None);
+    }
+    // counted from the first byte of the request, which may have been buffered earlier
+    let header_deadline = Instant::now() + timeouts.headers;
+
+    // keep reading until the parser has seen the whole header section
+    let (parsed_headers, consumed) = loop {
+        if let Some(parsed) = parser::parse_head(buffer, limits)? {
+            break parsed;
+        }
+        buffer.reserve(READ_CHUNK_SIZE);
+        let read = time::timeout_at(header_deadline, stream.read_buf(buffer))
+            .await
+            .map_err(|_| RequestError::Timeout)??;
+        if read == 0 {
+            if buffer.iter().all(|c| *c == b'\r' || *c == b'\n') {
+                return Ok(None);
+            }
+            return Err(Error::new(ErrorKind::BrokenPipe, "Connection closed").into());
+        }
+    };
+    buffer.advance(consumed);
+    Ok(Some(parsed_headers))
+}
+
+// Second half of `read_request`, the body announced by `headers`
+pub async fn read_body<S>(
+    stream: &mut S,
+    buffer: &mut BytesMut,
+    headers: RequestHeaders,
+    limits: &Limits,
+    timeouts: &Timeouts,
+) -> Result<ParsedRequest, RequestError>
+where
+    S: AsyncRead + Unpin,
+{
+    let mut reader = BodyReader {
+        stream,
+        timeouts,
+        start: Instant::now(),
+        received: 0,
+    };
+    let (body, trailers) = match body_length(&headers)? {
+        BodyLength::Fixed(length) => (
+            read_fixed_body(&mut reader, buffer, length, limits).await?,
+            HeaderMap::new(),
+        ),
+        BodyLength::Chunked => read_chunked_body(&mut reader, buffer, limits).await?,
+    };
 
     Ok(ParsedRequest {
-        headers: parsed_headers,
-        body: if body_str.is_empty() {
-            None
-        } else {
-            Some(body_str)
-        },
+        headers,
+        body,
+        trailers,
+        params: PathParams::new(),
     })
 }
 
+pub async fn parse_stream<S>(stream: &mut S) -> Result<ParsedRequest, RequestError>
+where
+    S: AsyncRead + Unpin,
+{
+    let mut buffer = BytesMut::new();
+    read_request(
+        stream,
+        &mut buffer,
+        &Limits::default(),
+        &Timeouts::default(),
+    )
+    .await?
+    .ok_or_else(|| Error::new(ErrorKind::BrokenPipe, "Connection closed").into())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -79,10 +469,10 @@ mod tests {
     async fn test_parse_get_request() {
         let headers = "GET /home HTTP/1.1\r\nUser-Agent: TestAgent\r\n\r\n";
         let parsed = parse_request_headers(headers).await.unwrap();
-        assert_eq!(parsed.method, "GET");
-        assert_eq!(parsed.path, "/home");
-        assert_eq!(parsed.user_agent, "TestAgent");
-        assert_eq!(parsed.content_length, None);
+        assert_eq!(parsed.method, Method::Get);
+        assert_eq!(parsed.path(), "/home");
+        assert_eq!(parsed.user_agent(), Some("TestAgent"));
+        assert_eq!(parsed.content_length(), None);
     }
 
     #[tokio::test]
@@ -90,19 +480,359 @@ mod tests {
         let headers =
             "POST /submit HTTP/1.1\r\nUser-Agent: TestAgent\r\nContent-Length: 15\r\n\r\n";
         let parsed = parse_request_headers(headers).await.unwrap();
-        assert_eq!(parsed.method, "POST");
-        assert_eq!(parsed.path, "/submit");
-        assert_eq!(parsed.user_agent, "TestAgent");
-        assert_eq!(parsed.content_length, Some(15));
+        assert_eq!(parsed.method, Method::Post);
+        assert_eq!(parsed.path(), "/submit");
+        assert_eq!(parsed.user_agent(), Some("TestAgent"));
+        assert_eq!(parsed.content_length(), Some(15));
     }
 
     #[tokio::test]
     async fn test_parse_malformed_request() {
         let headers = "INVALID REQUEST\r\n";
+        assert!(parse_request_headers(headers).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_parse_all_methods() {
+        for (token, method) in [
+            ("GET", Method::Get),
+            ("HEAD", Method::Head),
+            ("POST", Method::Post),
+            ("PUT", Method::Put),
+            ("DELETE", Method::Delete),
+            ("OPTIONS", Method::Options),
+            ("PATCH", Method::Patch),
+            ("TRACE", Method::Trace),
+            ("PROPFIND", Method::Extension("PROPFIND".to_string())),
+        ] {
+            let headers = format!("{} /files/a.txt HTTP/1.1\r\n\r\n", token);
+            let parsed = parse_request_headers(&headers).await.unwrap();
+            assert_eq!(parsed.method, method);
+            assert_eq!(parsed.path(), "/files/a.txt");
+            assert_eq!(parsed.version, Version::Http11);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_parse_arbitrary_headers() {
+        let headers = "GET / HTTP/1.1\r\nHost: localhost:4221\r\naccept: */*\r\nAuthorization: Bearer abc\r\nX-Request-Id:  42 \r\nCookie: a=1; b=2\r\nCookie: c=3\r\n\r\n";
         let parsed = parse_request_headers(headers).await.unwrap();
-        assert_eq!(parsed.method, "");
-        assert_eq!(parsed.path, "");
-        assert_eq!(parsed.user_agent, "");
-        assert_eq!(parsed.content_length, None);
+        assert_eq!(parsed.host(), Some("localhost:4221"));
+        assert_eq!(parsed.accept(), Some("*/*"));
+        assert_eq!(parsed.authorization(), Some("Bearer abc"));
+        assert_eq!(parsed.header("x-request-id"), Some("42"));
+        assert_eq!(parsed.headers.get_all("cookie").count(), 2);
+        assert_eq!(parsed.cookie("b"), Some("2"));
+        assert_eq!(parsed.cookie("c"), Some("3"));
+        assert_eq!(parsed.cookie("d"), None);
+    }
+
+    #[tokio::test]
+    async fn test_parse_stream_binary_body() {
+        let body: Vec<u8> = vec![0x89, b'P', b'N', b'G', 0x00, 0xff, 0xfe, b'\r', b'\n'];
+        let mut raw = format!(
+            "POST /files/image.png HTTP/1.1\r\nContent-Length: {}\r\n\r\n",
+            body.len()
+        )
+        .into_bytes();
+        raw.extend_from_slice(&body);
+
+        let mut stream = raw.as_slice();
+        let parsed = parse_stream(&mut stream).await.unwrap();
+        assert_eq!(parsed.body.as_ref(), body.as_slice());
+        assert!(parsed.text().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_parse_stream_text_and_json_body() {
+        let raw = "POST /api HTTP/1.1\r\nContent-Length: 12\r\n\r\n{\"name\":\"a\"}";
+        let mut stream = raw.as_bytes();
+        let parsed = parse_stream(&mut stream).await.unwrap();
+        assert!(parsed.has_body());
+        assert_eq!(parsed.text().unwrap(), "{\"name\":\"a\"}");
+
+        let value: serde_json::Value = parsed.json().unwrap();
+        assert_eq!(value["name"], "a");
+    }
+
+    #[tokio::test]
+    async fn test_parse_stream_without_body() {
+        let mut stream = "GET / HTTP/1.1\r\n\r\n".as_bytes();
+        let parsed = parse_stream(&mut stream).await.unwrap();
+        assert!(!parsed.has_body());
+    }
+
+    #[tokio::test]
+    async fn test_parse_stream_chunked_body() {
+        let raw = "POST /files/upload HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n\
+                   5;name=value\r\nhello\r\n\
+                   7\r\n, world\r\n\
+                   0\r\nExpires: never\r\n\r\n";
+        let mut stream = raw.as_bytes();
+        let parsed = parse_stream(&mut stream).await.unwrap();
+        assert_eq!(parsed.text().unwrap(), "hello, world");
+        assert_eq!(parsed.trailers.get("Expires"), Some("never"));
+    }
+
+    #[tokio::test]
+    async fn test_parse_stream_chunked_body_split_reads() {
+        let raw = "POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n3\r\nabc\r\n0\r\n\r\n";
+        // deliver the request a few bytes at a time to exercise every resume point
+        let mut stream = split_stream(raw.as_bytes(), 3);
+        let parsed = parse_stream(&mut stream).await.unwrap();
+        assert_eq!(parsed.text().unwrap(), "abc");
+        assert!(parsed.trailers.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_parse_stream_rejects_malformed_chunks() {
+        for raw in [
+            "POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\nzz\r\n",
+            "POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n3\r\nabcd\r\n0\r\n\r\n",
+        ] {
+            let mut stream = raw.as_bytes();
+            assert!(matches!(
+                parse_stream(&mut stream).await,
+                Err(RequestError::Parse(ParseError::InvalidChunk))
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_parse_stream_rejects_ambiguous_length() {
+        let cases = [
+            (
+                "POST / HTTP/1.1\r\nContent-Length: 3\r\nTransfer-Encoding: chunked\r\n\r\n",
+                ParseError::ConflictingLength,
+            ),
+            (
+                "POST / HTTP/1.1\r\nContent-Length: 3\r\nContent-Length: 4\r\n\r\n",
+                ParseError::InvalidContentLength,
+            ),
+            (
+                "POST / HTTP/1.1\r\nContent-Length: -1\r\n\r\n",
+                ParseError::InvalidContentLength,
+            ),
+            (
+                "POST / HTTP/1.1\r\nTransfer-Encoding: gzip, chunked\r\n\r\n",
+                ParseError::UnsupportedTransferCoding,
+            ),
+            (
+                "POST / HTTP/1.1\r\nTransfer-Encoding: chunked, chunked\r\n\r\n",
+                ParseError::InvalidTransferEncoding,
+            ),
+        ];
+        for (raw, expected) in cases {
+            let mut stream = raw.as_bytes();
+            match parse_stream(&mut stream).await {
+                Err(RequestError::Parse(e)) => assert_eq!(e, expected),
+                _ => panic!("expected {:?} for {:?}", expected, raw),
+            }
+        }
+    }
+
+    // Stream that yields at most `step` bytes per read
+    fn split_stream(data: &[u8], step: usize) -> impl AsyncRead + Unpin {
+        let (mut writer, reader) = tokio::io::duplex(step);
+        let data = data.to_vec();
+        tokio::spawn(async move {
+            use tokio::io::AsyncWriteExt;
+            writer.write_all(&data).await.unwrap();
+        });
+        reader
+    }
+
+    #[tokio::test]
+    async fn test_keep_alive() {
+        for (raw, expected) in [
+            ("GET / HTTP/1.1\r\n\r\n", true),
+            ("GET / HTTP/1.1\r\nConnection: close\r\n\r\n", false),
+            (
+                "GET / HTTP/1.1\r\nConnection: Upgrade, Close\r\n\r\n",
+                false,
+            ),
+            ("GET / HTTP/1.0\r\n\r\n", false),
+            ("GET / HTTP/1.0\r\nConnection: keep-alive\r\n\r\n", true),
+        ] {
+            let parsed = parse_request_headers(raw).await.unwrap();
+            assert_eq!(parsed.keep_alive(), expected, "{}", raw);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_read_pipelined_requests() {
+        let raw = "POST /a HTTP/1.1\r\nContent-Length: 3\r\n\r\nabcGET /b HTTP/1.1\r\n\r\n";
+        let mut stream = raw.as_bytes();
+        let mut buffer = BytesMut::new();
+        let limits = Limits::default();
+        let timeouts = Timeouts::default();
+
+        let first = read_request(&mut stream, &mut buffer, &limits, &timeouts)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(first.headers.path(), "/a");
+        assert_eq!(first.text().unwrap(), "abc");
+
+        let second = read_request(&mut stream, &mut buffer, &limits, &timeouts)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(second.headers.path(), "/b");
+        assert!(!second.has_body());
+
+        assert!(read_request(&mut stream, &mut buffer, &limits, &timeouts)
+            .await
+            .unwrap()
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn test_read_request_enforces_body_limit() {
+        let limits = Limits {
+            max_body_size: 8,
+            ..Limits::default()
+        };
+        let timeouts = Timeouts::default();
+        let read = |raw: &'static str| async move {
+            let mut buffer = BytesMut::new();
+            read_request(&mut raw.as_bytes(), &mut buffer, &limits, &timeouts).await
+        };
+
+        let result = read("POST / HTTP/1.1\r\nContent-Length: 8\r\n\r\n12345678").await;
+        assert_eq!(result.unwrap().unwrap().text().unwrap(), "12345678");
+
+        // rejected from the header alone, without waiting for a body that never comes
+        let raw = "POST / HTTP/1.1\r\nContent-Length: 99999999999\r\n\r\n";
+        assert!(matches!(
+            read(raw).await,
+            Err(RequestError::Parse(ParseError::BodyTooLarge))
+        ));
+
+        let raw = "POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n5\r\n12345\r\n4\r\n";
+        assert!(matches!(
+            read(raw).await,
+            Err(RequestError::Parse(ParseError::BodyTooLarge))
+        ));
+        let raw = "POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n5\r\n12345\r\n3\r\n678\r\n0\r\n\r\n";
+        assert_eq!(
+            read(raw).await.unwrap().unwrap().text().unwrap(),
+            "12345678"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_read_request_limits_chunk_framing() {
+        let limits = Limits {
+            max_header_bytes: 64,
+            ..Limits::default()
+        };
+        let timeouts = Timeouts::default();
+        let read = |raw: String| async move {
+            let mut buffer = BytesMut::new();
+            read_request(&mut raw.as_bytes(), &mut buffer, &limits, &timeouts).await
+        };
+        let head = "POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n";
+
+        // extensions far longer than the data they come with
+        let raw = format!("{}{}0\r\n\r\n", head, "1;a=bbbbbbbbbb\r\nx\r\n".repeat(10));
+        assert!(matches!(
+            read(raw).await,
+            Err(RequestError::Parse(ParseError::BodyTooLarge))
+        ));
+        // a chunk line that never ends
+        let raw = format!("{}1;{}", head, "a".repeat(8 * 1024));
+        assert!(matches!(
+            read(raw).await,
+            Err(RequestError::Parse(ParseError::InvalidChunk))
+        ));
+        // trailers count like headers
+        let raw = format!("{}0\r\nX-Big: {}\r\n\r\n", head, "a".repeat(64));
+        assert!(matches!(
+            read(raw).await,
+            Err(RequestError::Parse(ParseError::HeadersTooLarge))
+        ));
+
+        let raw = format!("{}{}0\r\n\r\n", head, "4\r\nabcd\r\n".repeat(20));
+        assert_eq!(read(raw).await.unwrap().unwrap().body.len(), 80);
+    }
+
+    // Sends `parts` one after another with `pause` in between, then keeps the stream open
+    fn slow_stream(parts: Vec<&'static str>, pause: Duration) -> impl AsyncRead + Unpin {
+        let (mut writer, reader) = tokio::io::duplex(1024);
+        tokio::spawn(async move {
+            use tokio::io::AsyncWriteExt;
+            for part in parts {
+                writer.write_all(part.as_bytes()).await.unwrap();
+                time::sleep(pause).await;
+            }
+            time::sleep(Duration::from_secs(3600)).await;
+        });
+        reader
+    }
+
+    async fn read_slowly(
+        parts: Vec<&'static str>,
+        pause: Duration,
+    ) -> Result<Option<ParsedRequest>, RequestError> {
+        let timeouts = Timeouts {
+            idle: Duration::from_secs(5),
+            headers: Duration::from_secs(10),
+            body: Duration::from_secs(10),
+            min_body_rate: 1,
+        };
+        let mut stream = slow_stream(parts, pause);
+        let mut buffer = BytesMut::new();
+        read_request(&mut stream, &mut buffer, &Limits::default(), &timeouts).await
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_idle_timeout() {
+        let result = read_slowly(vec![], Duration::ZERO).await;
+        assert!(matches!(result, Err(RequestError::IdleTimeout)));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_header_timeout() {
+        // a slowloris client keeps sending header lines but never finishes
+        let parts = vec!["GET / HTTP/1.1\r\n", "A: 1\r\n", "B: 2\r\n", "C: 3\r\n"];
+        let started = Instant::now();
+        let result = read_slowly(parts, Duration::from_secs(4)).await;
+        assert!(matches!(result, Err(RequestError::Timeout)));
+        assert_eq!(started.elapsed(), Duration::from_secs(10));
+
+        let parts = vec!["GET / HTTP/1.1\r\n", "A: 1\r\n", "\r\n"];
+        let result = read_slowly(parts, Duration::from_secs(4)).await;
+        assert_eq!(result.unwrap().unwrap().headers.header("A"), Some("1"));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_body_rate() {
+        let head = "POST / HTTP/1.1\r\nContent-Length: 4\r\n\r\n";
+        // every byte buys another second on top of the initial ten
+        let parts = vec![head, "a", "b", "c", "d"];
+        let result = read_slowly(parts, Duration::from_secs(3)).await;
+        assert_eq!(result.unwrap().unwrap().text().unwrap(), "abcd");
+
+        let parts = vec![head, "a", "b", "c", "d"];
+        let result = read_slowly(parts, Duration::from_secs(6)).await;
+        assert!(matches!(result, Err(RequestError::Timeout)));
+
+        // chunk framing earns no time, only the data in the chunks does
+        let head = "POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n";
+        let parts = vec![
+            head,
+            "1;a=b\r\n",
+            "x",
+            "\r\n1;a=b\r\n",
+            "y",
+            "\r\n0\r\n\r\n",
+        ];
+        let result = read_slowly(parts, Duration::from_secs(2)).await;
+        assert_eq!(result.unwrap().unwrap().text().unwrap(), "xy");
+
+        let parts = vec![head, "1;a=b", "bbbbbbbb", "bbbbbbbb", "bbbbbbbb", "\r\n"];
+        let result = read_slowly(parts, Duration::from_secs(3)).await;
+        assert!(matches!(result, Err(RequestError::Timeout)));
     }
 }
@@ -1,64 +1,378 @@
 use std::fmt;
+use std::io;
+use tokio::io::{AsyncWrite, AsyncWriteExt};
 
+use crate::http::{HeaderMap, Version};
 use crate::HTTPBody;
 use crate::HTTPStatus;
 
-const LINE_FEED: &'static str = "\r\n";
+const LINE_FEED: &str = "\r\n";
 
 pub struct HTTPResponse {
+    pub version: Version,
     pub status: HTTPStatus,
+    pub headers: HeaderMap,
     pub body: Option<HTTPBody>,
 }
 
-impl fmt::Display for HTTPResponse {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let mut response = format!("{}{}", self.status, LINE_FEED);
-
-        if let Some(ref body) = self.body {
-            response.push_str(&format!(
-                "{}{}Content-Length: {}{}{}{}",
-                body.content_type,
-                LINE_FEED,
-                body.body.len(),
-                LINE_FEED,
-                LINE_FEED,
-                &body.body,
-            ));
+impl HTTPResponse {
+    pub fn new(status: HTTPStatus) -> Self {
+        HTTPResponse {
+            version: Version::default(),
+            status,
+            headers: HeaderMap::new(),
+            body: None,
+        }
+    }
+
+    pub fn builder() -> HTTPResponseBuilder {
+        HTTPResponseBuilder {
+            response: HTTPResponse::new(HTTPStatus::Ok),
+        }
+    }
+
+    // Body that is actually sent, 1xx, 204 and 304 responses never carry one
+    fn sent_body(&self) -> Option<&HTTPBody> {
+        self.body.as_ref().filter(|_| self.status.allows_body())
+    }
+
+    // Streams of unknown length are sent chunked to HTTP/1.1 clients
+    fn is_chunked(&self) -> bool {
+        self.version == Version::Http11 && self.sent_body().is_some_and(|b| b.body.len().is_none())
+    }
+
+    // HTTP/1.0 clients do not understand chunked encoding, a body of unknown length is
+    // delimited by closing the connection instead
+    pub fn is_close_delimited(&self) -> bool {
+        self.version == Version::Http10 && self.sent_body().is_some_and(|b| b.body.len().is_none())
+    }
+
+    // Status line and header section including the empty line that ends it.
+    // The framing headers are always derived from the body so the client can find the end of
+    // the response on a persistent connection, a Content-Type header set by the handler takes
+    // precedence over the body's content type.
+    pub fn head(&self) -> String {
+        let mut response = format!("{}{}", self.status.status_line(self.version), LINE_FEED);
+
+        for (name, value) in self.headers.iter() {
+            if name.eq_ignore_ascii_case("Content-Length")
+                || name.eq_ignore_ascii_case("Transfer-Encoding")
+            {
+                continue;
+            }
+            response.push_str(&format!("{}: {}{}", name, value, LINE_FEED));
+        }
+        match self.sent_body() {
+            Some(body) => {
+                if !self.headers.contains("Content-Type") {
+                    response.push_str(&format!("{}{}", body.content_type, LINE_FEED));
+                }
+                match body.body.len() {
+                    Some(length) => {
+                        response.push_str(&format!("Content-Length: {}{}", length, LINE_FEED))
+                    }
+                    None if self.is_chunked() => {
+                        response.push_str(&format!("Transfer-Encoding: chunked{}", LINE_FEED))
+                    }
+                    None => {}
+                }
+            }
+            // 1xx and 204 responses must not have Content-Length, 304 has no content to describe
+            None if self.status.allows_body() => {
+                response.push_str(&format!("Content-Length: 0{}", LINE_FEED))
+            }
+            None => {}
         }
         response.push_str(LINE_FEED);
-        write!(f, "{}", response)
+        response
+    }
+
+    // Serialized response with in-memory bodies copied byte-for-byte.
+    // Streamed bodies are not included, use `write_to` to send those.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut response = self.head().into_bytes();
+        if let Some(bytes) = self.sent_body().and_then(|b| b.body.as_bytes()) {
+            response.extend_from_slice(bytes);
+        }
+        response
+    }
+
+    // Write the response to the socket, streamed bodies are copied as the client reads them
+    pub async fn write_to<W>(self, writer: &mut W) -> io::Result<()>
+    where
+        W: AsyncWrite + Unpin,
+    {
+        writer.write_all(self.head().as_bytes()).await?;
+        let chunked = self.is_chunked();
+        if let Some(body) = self.body.filter(|_| self.status.allows_body()) {
+            body.body.write_to(writer, chunked).await?;
+        }
+        writer.flush().await
+    }
+
+    // Write only the status line and headers, as the answer to a HEAD request. The headers are
+    // the same as for the full response, including the length of the body that is left out.
+    pub async fn write_head_to<W>(self, writer: &mut W) -> io::Result<()>
+    where
+        W: AsyncWrite + Unpin,
+    {
+        writer.write_all(self.head().as_bytes()).await?;
+        writer.flush().await
+    }
+}
+
+// Fluent construction of responses, e.g.
+// `HTTPResponse::builder().status(HTTPStatus::Created).header("Location", "/files/a").build()`
+pub struct HTTPResponseBuilder {
+    response: HTTPResponse,
+}
+
+impl HTTPResponseBuilder {
+    pub fn version(mut self, version: Version) -> Self {
+        self.response.version = version;
+        self
+    }
+
+    pub fn status(mut self, status: HTTPStatus) -> Self {
+        self.response.status = status;
+        self
+    }
+
+    // Adds a header, repeated names such as Set-Cookie are all sent.
+    // Headers that would break the response framing (invalid names, CR or LF in the value)
+    // are dropped.
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.response.headers.append(name, value);
+        self
+    }
+
+    pub fn body(mut self, body: HTTPBody) -> Self {
+        self.response.body = Some(body);
+        self
+    }
+
+    pub fn build(self) -> HTTPResponse {
+        self.response
+    }
+}
+
+// Text form of the response, used for logging. Bodies that are not valid UTF-8 are shown lossily.
+impl fmt::Display for HTTPResponse {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", String::from_utf8_lossy(&self.to_bytes()))
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::body::Body;
     use crate::http::HTTPContentType;
 
     #[test]
     fn test_http_response_without_body() {
         let response = HTTPResponse {
+            version: Version::Http11,
             status: HTTPStatus::Ok,
+            headers: HeaderMap::new(),
             body: None,
         };
 
-        let expected_output = format!("HTTP/1.1 200 OK\r\n\r\n");
+        let expected_output = "HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n".to_string();
         assert_eq!(format!("{}", response), expected_output);
     }
 
     #[test]
     fn test_http_response_with_body() {
         let response = HTTPResponse {
+            version: Version::Http11,
             status: HTTPStatus::NotFound,
+            headers: HeaderMap::new(),
             body: Some(HTTPBody {
-                body: "Page not found".to_string(),
+                body: Body::from("Page not found"),
                 content_type: HTTPContentType::PlainText,
             }),
         };
 
-        let expected_output = format!(
-            "HTTP/1.1 404 Not Found\r\nContent-Type: text/plain\r\nContent-Length: 14\r\n\r\nPage not found\r\n"
-        );
+        let expected_output = "HTTP/1.1 404 Not Found\r\nContent-Type: text/plain\r\nContent-Length: 14\r\n\r\nPage not found";
+        assert_eq!(format!("{}", response), expected_output);
+    }
+
+    #[test]
+    fn test_http_response_binary_body() {
+        let payload: &[u8] = &[0x00, 0xff, 0x89, 0x50];
+        let response = HTTPResponse {
+            version: Version::Http11,
+            status: HTTPStatus::Ok,
+            headers: HeaderMap::new(),
+            body: Some(HTTPBody {
+                body: Body::from(payload.to_vec()),
+                content_type: HTTPContentType::File,
+            }),
+        };
+
+        let mut expected_output =
+            b"HTTP/1.1 200 OK\r\nContent-Type: application/octet-stream\r\nContent-Length: 4\r\n\r\n"
+                .to_vec();
+        expected_output.extend_from_slice(payload);
+        assert_eq!(response.to_bytes(), expected_output);
+    }
+
+    #[test]
+    fn test_http_response_with_headers() {
+        let mut headers = HeaderMap::new();
+        headers.append("Connection", "close");
+        let response = HTTPResponse {
+            version: Version::Http11,
+            status: HTTPStatus::Ok,
+            headers,
+            body: None,
+        };
+
+        let expected_output = "HTTP/1.1 200 OK\r\nConnection: close\r\nContent-Length: 0\r\n\r\n";
+        assert_eq!(format!("{}", response), expected_output);
+    }
+
+    #[test]
+    fn test_http_response_builder() {
+        let response = HTTPResponse::builder()
+            .status(HTTPStatus::Created)
+            .header("Location", "/files/a.txt")
+            .header("Set-Cookie", "a=1")
+            .header("Set-Cookie", "b=2")
+            .header("Cache-Control", "no-cache")
+            .body(HTTPBody::text("created"))
+            .build();
+
+        let expected_output = "HTTP/1.1 201 Created\r\nLocation: /files/a.txt\r\nSet-Cookie: a=1\r\nSet-Cookie: b=2\r\nCache-Control: no-cache\r\nContent-Type: text/plain\r\nContent-Length: 7\r\n\r\ncreated";
         assert_eq!(format!("{}", response), expected_output);
     }
+
+    #[test]
+    fn test_http_response_builder_rejects_header_injection() {
+        let response = HTTPResponse::builder()
+            .header("X-Bad", "a\r\nSet-Cookie: evil=1")
+            .header("Bad Name", "value")
+            .header("X-Good", "value")
+            .build();
+
+        assert_eq!(response.headers.len(), 1);
+        assert_eq!(response.headers.get("x-good"), Some("value"));
+    }
+
+    #[test]
+    fn test_http_response_header_overrides() {
+        let response = HTTPResponse::builder()
+            .header("Content-Type", "application/json")
+            .header("Content-Length", "999")
+            .body(HTTPBody::text("{}"))
+            .build();
+
+        let expected_output =
+            "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: 2\r\n\r\n{}";
+        assert_eq!(format!("{}", response), expected_output);
+    }
+
+    #[test]
+    fn test_http_response_version() {
+        let response = HTTPResponse::builder()
+            .version(Version::Http10)
+            .status(HTTPStatus::MovedPermanently)
+            .header("Location", "/new")
+            .build();
+
+        let expected_output =
+            "HTTP/1.0 301 Moved Permanently\r\nLocation: /new\r\nContent-Length: 0\r\n\r\n";
+        assert_eq!(format!("{}", response), expected_output);
+    }
+
+    #[test]
+    fn test_http_response_without_content() {
+        let response = HTTPResponse::builder()
+            .status(HTTPStatus::NoContent)
+            .body(HTTPBody::text("ignored"))
+            .build();
+        assert_eq!(format!("{}", response), "HTTP/1.1 204 No Content\r\n\r\n");
+
+        let response = HTTPResponse::builder()
+            .status(HTTPStatus::NotModified)
+            .header("ETag", "\"abc\"")
+            .build();
+        assert_eq!(
+            format!("{}", response),
+            "HTTP/1.1 304 Not Modified\r\nETag: \"abc\"\r\n\r\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_http_response_streams_known_length() {
+        let response = HTTPResponse::builder()
+            .body(HTTPBody::stream(
+                &b"streamed"[..],
+                Some(8),
+                HTTPContentType::File,
+            ))
+            .build();
+
+        let mut output = Vec::new();
+        response.write_to(&mut output).await.unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "HTTP/1.1 200 OK\r\nContent-Type: application/octet-stream\r\nContent-Length: 8\r\n\r\nstreamed"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_http_response_streams_unknown_length_chunked() {
+        let response = HTTPResponse::builder()
+            .header("Transfer-Encoding", "gzip")
+            .body(HTTPBody::stream(
+                &b"streamed"[..],
+                None,
+                HTTPContentType::PlainText,
+            ))
+            .build();
+        assert!(!response.is_close_delimited());
+
+        let mut output = Vec::new();
+        response.write_to(&mut output).await.unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\nTransfer-Encoding: chunked\r\n\r\n8\r\nstreamed\r\n0\r\n\r\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_http_response_head_only() {
+        let response = HTTPResponse::builder()
+            .body(HTTPBody::text("not sent"))
+            .build();
+
+        let mut output = Vec::new();
+        response.write_head_to(&mut output).await.unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\nContent-Length: 8\r\n\r\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_http10_response_with_unknown_length_is_close_delimited() {
+        let response = HTTPResponse::builder()
+            .version(Version::Http10)
+            .body(HTTPBody::stream(
+                &b"streamed"[..],
+                None,
+                HTTPContentType::PlainText,
+            ))
+            .build();
+        assert!(response.is_close_delimited());
+
+        let mut output = Vec::new();
+        response.write_to(&mut output).await.unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "HTTP/1.0 200 OK\r\nContent-Type: text/plain\r\n\r\nstreamed"
+        );
+    }
 }
@@ -0,0 +1,521 @@
+use crate::handler::{Handler, HandlerFuture, Middleware, MiddlewareStack, Next};
+use crate::http::{HTTPStatus, Method};
+use crate::request::ParsedRequest;
+use crate::response::HTTPResponse;
+use crate::uri::Uri;
+
+use std::str::FromStr;
+use std::sync::Arc;
+
+// Values captured from `:name` and `*name` segments of the matched route, decoded
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PathParams {
+    entries: Vec<(String, String)>,
+    // name and decoded segments of a `*name` wildcard, kept apart since a segment may itself
+    // contain an encoded `/`
+    wildcard: Option<(String, Vec<String>)>,
+}
+
+impl PathParams {
+    pub fn new() -> Self {
+        PathParams {
+            entries: Vec::new(),
+            wildcard: None,
+        }
+    }
+
+    pub fn insert(&mut self, name: &str, value: &str) {
+        self.entries.push((name.to_string(), value.to_string()));
+    }
+
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.entries
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    // Segments behind parameter `name`, a single one for `:name`. The joined value of a
+    // wildcard cannot tell `a%2Fb` from `a/b`, file lookups should use this instead.
+    pub fn segments(&self, name: &str) -> Option<Vec<&str>> {
+        match &self.wildcard {
+            Some((key, rest)) if key == name => Some(rest.iter().map(String::as_str).collect()),
+            _ => self.get(name).map(|value| vec![value]),
+        }
+    }
+
+    // Parameter converted to `T`, None if it is missing or does not parse
+    pub fn parse<T: FromStr>(&self, name: &str) -> Option<T> {
+        self.get(name).and_then(|value| value.parse().ok())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Segment {
+    Literal(String),
+    Param(String),
+    // matches the remaining segments, including none at all
+    Wildcard(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Pattern {
+    segments: Vec<Segment>,
+}
+
+impl Pattern {
+    // Patterns are fixed when routes are registered, so a malformed one is a programming error
+    // and panics right there rather than when a request arrives
+    fn parse(pattern: &str) -> Self {
+        let segments: Vec<Segment> = split_path(pattern)
+            .map(|segment| {
+                if let Some(name) = segment.strip_prefix(':') {
+                    Segment::Param(name.to_string())
+                } else if let Some(name) = segment.strip_prefix('*') {
+                    Segment::Wildcard(name.to_string())
+                } else {
+                    Segment::Literal(segment.to_string())
+                }
+            })
+            .collect();
+        if let Some(i) = segments
+            .iter()
+            .position(|segment| matches!(segment, Segment::Wildcard(_)))
+        {
+            assert!(
+                i == segments.len() - 1,
+                "wildcard must be the last segment of route pattern {:?}",
+                pattern
+            );
+        }
+        Pattern { segments }
+    }
+
+    // Match the decoded segments of a request path
+    fn matches(&self, path: &[String]) -> Option<PathParams> {
+        let mut params = PathParams::new();
+        let mut parts = path.iter().map(String::as_str);
+
+        for segment in &self.segments {
+            match segment {
+                Segment::Wildcard(name) => {
+                    let rest: Vec<&str> = parts.collect();
+                    params.insert(name, &rest.join("/"));
+                    let rest = rest.into_iter().map(str::to_string).collect();
+                    // always the last segment, see `parse`
+                    params.wildcard = Some((name.clone(), rest));
+                    return Some(params);
+                }
+                Segment::Literal(literal) => {
+                    if parts.next()? != literal {
+                        return None;
+                    }
+                }
+                Segment::Param(name) => {
+                    let part = parts.next().filter(|part| !part.is_empty())?;
+                    params.insert(name, part);
+                }
+            }
+        }
+
+        if parts.next().is_some() {
+            return None;
+        }
+        Some(params)
+    }
+}
+
+fn split_path(path: &str) -> impl Iterator<Item = &str> {
+    path.strip_prefix('/').unwrap_or(path).split('/')
+}
+
+struct Route {
+    method: Method,
+    pattern: Pattern,
+    handler: Arc<dyn Handler>,
+}
+
+// Dispatches requests to handlers by method and path. Patterns are matched in registration
+// order, `:name` captures a single segment and a trailing `*name` captures the rest of the path.
+// Paths that match a route under another method are answered with 405 and an Allow header,
+// OPTIONS requests are answered automatically unless a route handles them.
+// Middleware added with `layer` runs around every request, including the 404 and 405 answers.
+#[derive(Default)]
+pub struct Router {
+    routes: Vec<Route>,
+    middleware: MiddlewareStack,
+}
+
+impl Router {
+    pub fn new() -> Self {
+        Router {
+            routes: Vec::new(),
+            middleware: MiddlewareStack::default(),
+        }
+    }
+
+    pub fn route<H: Handler>(mut self, method: Method, path: &str, handler: H) -> Self {
+        self.routes.push(Route {
+            method,
+            pattern: Pattern::parse(path),
+            handler: Arc::new(handler),
+        });
+        self
+    }
+
+    pub fn get<H: Handler>(self, path: &str, handler: H) -> Self {
+        self.route(Method::Get, path, handler)
+    }
+
+    pub fn head<H: Handler>(self, path: &str, handler: H) -> Self {
+        self.route(Method::Head, path, handler)
+    }
+
+    pub fn post<H: Handler>(self, path: &str, handler: H) -> Self {
+        self.route(Method::Post, path, handler)
+    }
+
+    pub fn put<H: Handler>(self, path: &str, handler: H) -> Self {
+        self.route(Method::Put, path, handler)
+    }
+
+    pub fn delete<H: Handler>(self, path: &str, handler: H) -> Self {
+        self.route(Method::Delete, path, handler)
+    }
+
+    pub fn patch<H: Handler>(self, path: &str, handler: H) -> Self {
+        self.route(Method::Patch, path, handler)
+    }
+
+    pub fn options<H: Handler>(self, path: &str, handler: H) -> Self {
+        self.route(Method::Options, path, handler)
+    }
+
+    // Wrap every route in `middleware`, layers run in the order they were added.
+    // Use `HandlerExt::layer` to wrap a single route instead.
+    pub fn layer<M: Middleware>(mut self, middleware: M) -> Self {
+        Arc::make_mut(&mut self.middleware).push(Arc::new(middleware));
+        self
+    }
+
+    // Methods with a route for `path`, in registration order and without duplicates, or of
+    // any route for the asterisk of `OPTIONS *`. HEAD follows GET when it is not routed
+    // explicitly.
+    fn allowed_methods(&self, uri: &Uri) -> Vec<Method> {
+        let mut methods: Vec<Method> = Vec::new();
+        for route in &self.routes {
+            let matches = uri.is_asterisk() || route.pattern.matches(uri.segments()).is_some();
+            if matches && !methods.contains(&route.method) {
+                methods.push(route.method.clone());
+            }
+        }
+        if let Some(get) = methods.iter().position(|method| *method == Method::Get) {
+            if !methods.contains(&Method::Head) {
+                methods.insert(get + 1, Method::Head);
+            }
+        }
+        methods
+    }
+
+    // Handler for the request, with the path parameters of the matched route stored in it.
+    // Requests without a matching route get a handler answering 404, 405 or OPTIONS.
+    fn resolve(&self, request: &mut ParsedRequest) -> Arc<dyn Handler> {
+        let uri = request.headers.uri.clone();
+
+        // HEAD is answered by the GET route unless a route handles it explicitly, the
+        // connection leaves out the body
+        let mut methods = vec![request.headers.method.clone()];
+        if request.headers.method == Method::Head {
+            methods.push(Method::Get);
+        }
+        for method in &methods {
+            for route in self.routes.iter().filter(|route| route.method == *method) {
+                if let Some(params) = route.pattern.matches(uri.segments()) {
+                    request.params = params;
+                    return route.handler.clone();
+                }
+            }
+        }
+
+        let mut allowed = self.allowed_methods(&uri);
+        if allowed.is_empty() {
+            return Arc::new(|_| async { HTTPResponse::new(HTTPStatus::NotFound) });
+        }
+        if !allowed.contains(&Method::Options) {
+            allowed.push(Method::Options);
+        }
+        let allow = allowed
+            .iter()
+            .map(Method::as_str)
+            .collect::<Vec<_>>()
+            .join(", ");
+
+        let status = if request.headers.method == Method::Options {
+            HTTPStatus::NoContent
+        } else {
+            HTTPStatus::MethodNotAllowed
+        };
+        Arc::new(move |_| {
+            let response = HTTPResponse::builder()
+                .status(status.clone())
+                .header("Allow", &allow)
+                .build();
+            async move { response }
+        })
+    }
+
+    pub async fn handle(&self, request: ParsedRequest) -> HTTPResponse {
+        self.call(request).await
+    }
+}
+
+impl Handler for Router {
+    fn call(&self, mut request: ParsedRequest) -> HandlerFuture {
+        let endpoint = self.resolve(&mut request);
+        Next::new(self.middleware.clone(), endpoint).run(request)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handler::HandlerExt;
+    use crate::http::HTTPBody;
+    use crate::request::parse_stream;
+    use std::future::Future;
+
+    async fn request(method: &str, path: &str) -> ParsedRequest {
+        let raw = format!("{} {} HTTP/1.1\r\n\r\n", method, path);
+        parse_stream(&mut raw.as_bytes()).await.unwrap()
+    }
+
+    async fn body_text(response: HTTPResponse) -> String {
+        String::from_utf8(response.to_bytes())
+            .unwrap()
+            .split("\r\n\r\n")
+            .nth(1)
+            .unwrap()
+            .to_string()
+    }
+
+    fn path(path: &str) -> Vec<String> {
+        Uri::parse(path).unwrap().segments().to_vec()
+    }
+
+    fn echo_params(request: ParsedRequest) -> impl Future<Output = HTTPResponse> {
+        let text = format!("{:?}", request.params);
+        async move { HTTPResponse::builder().body(HTTPBody::text(text)).build() }
+    }
+
+    #[test]
+    fn test_pattern_matching() {
+        let pattern = Pattern::parse("/files/:name");
+        assert_eq!(
+            pattern.matches(&path("/files/a.txt")).unwrap().get("name"),
+            Some("a.txt")
+        );
+        assert!(pattern.matches(&path("/files/")).is_none());
+        assert!(pattern.matches(&path("/files")).is_none());
+        assert!(pattern.matches(&path("/files/a/b")).is_none());
+        assert!(pattern.matches(&path("/other/a")).is_none());
+
+        let pattern = Pattern::parse("/static/*path");
+        assert_eq!(
+            pattern
+                .matches(&path("/static/css/site.css"))
+                .unwrap()
+                .get("path"),
+            Some("css/site.css")
+        );
+        assert_eq!(
+            pattern.matches(&path("/static/")).unwrap().get("path"),
+            Some("")
+        );
+        assert_eq!(
+            pattern.matches(&path("/static")).unwrap().get("path"),
+            Some("")
+        );
+
+        let pattern = Pattern::parse("/");
+        assert!(pattern.matches(&path("/")).unwrap().is_empty());
+        assert!(pattern.matches(&path("/a")).is_none());
+    }
+
+    #[test]
+    fn test_params_are_decoded() {
+        let params = Pattern::parse("/echo/:text")
+            .matches(&path("/echo/hello%20world"))
+            .unwrap();
+        assert_eq!(params.get("text"), Some("hello world"));
+        assert_eq!(params.segments("text"), Some(vec!["hello world"]));
+
+        // an encoded slash joins the wildcard value but stays inside its segment
+        let params = Pattern::parse("/files/*path")
+            .matches(&path("/files/a%2Fb/c%20d.txt"))
+            .unwrap();
+        assert_eq!(params.get("path"), Some("a/b/c d.txt"));
+        assert_eq!(params.segments("path"), Some(vec!["a/b", "c d.txt"]));
+        assert_eq!(params.segments("missing"), None);
+
+        // literals are compared with the decoded path
+        assert!(Pattern::parse("/user-agent")
+            .matches(&path("/user%2Dagent"))
+            .is_some());
+    }
+
+    #[test]
+    #[should_panic(expected = "wildcard must be the last segment")]
+    fn test_wildcard_must_be_last() {
+        Router::new().get("/files/*path/edit", |_: ParsedRequest| async {
+            HTTPResponse::new(HTTPStatus::Ok)
+        });
+    }
+
+    #[test]
+    fn test_typed_params() {
+        let params = Pattern::parse("/users/:id/posts/:slug")
+            .matches(&path("/users/42/posts/hello"))
+            .unwrap();
+        assert_eq!(params.parse::<u32>("id"), Some(42));
+        assert_eq!(params.parse::<u32>("slug"), None);
+        assert_eq!(params.get("slug"), Some("hello"));
+        assert_eq!(params.get("missing"), None);
+    }
+
+    #[tokio::test]
+    async fn test_dispatch_by_method_and_path() {
+        let router = Router::new()
+            .get("/files/:name", echo_params)
+            .post("/files/:name", |_| async {
+                HTTPResponse::new(HTTPStatus::Created)
+            });
+
+        let response = router.handle(request("GET", "/files/a.txt").await).await;
+        assert_eq!(response.status, HTTPStatus::Ok);
+        assert!(body_text(response).await.contains("\"name\", \"a.txt\""));
+
+        let response = router.handle(request("POST", "/files/a.txt").await).await;
+        assert_eq!(response.status, HTTPStatus::Created);
+
+        let response = router.handle(request("GET", "/nothing").await).await;
+        assert_eq!(response.status, HTTPStatus::NotFound);
+    }
+
+    #[tokio::test]
+    async fn test_method_not_allowed() {
+        let router = Router::new()
+            .get("/files/:name", echo_params)
+            .post("/files/:name", echo_params)
+            .get("/files/:name", echo_params);
+
+        let response = router.handle(request("DELETE", "/files/a.txt").await).await;
+        assert_eq!(response.status, HTTPStatus::MethodNotAllowed);
+        assert_eq!(
+            response.headers.get("Allow"),
+            Some("GET, HEAD, POST, OPTIONS")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_automatic_options() {
+        let router = Router::new().get("/echo/*text", echo_params);
+
+        let response = router.handle(request("OPTIONS", "/echo/hi").await).await;
+        assert_eq!(response.status, HTTPStatus::NoContent);
+        assert_eq!(response.headers.get("Allow"), Some("GET, HEAD, OPTIONS"));
+
+        // an explicit OPTIONS route takes precedence
+        let router = router.options("/echo/*text", |_| async {
+            HTTPResponse::new(HTTPStatus::Ok)
+        });
+        let response = router.handle(request("OPTIONS", "/echo/hi").await).await;
+        assert_eq!(response.status, HTTPStatus::Ok);
+    }
+
+    #[tokio::test]
+    async fn test_server_wide_options() {
+        let router = Router::new()
+            .get("/", echo_params)
+            .post("/files/:name", echo_params)
+            .delete("/files/:name", echo_params);
+
+        let response = router.handle(request("OPTIONS", "*").await).await;
+        assert_eq!(response.status, HTTPStatus::NoContent);
+        assert_eq!(
+            response.headers.get("Allow"),
+            Some("GET, HEAD, POST, DELETE, OPTIONS")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_query_and_fragment_do_not_affect_routing() {
+        let router = Router::new().get("/files/:name", echo_params);
+
+        let response = router
+            .handle(request("GET", "/files/a.txt?download=1#top").await)
+            .await;
+        assert_eq!(response.status, HTTPStatus::Ok);
+        assert!(body_text(response).await.contains("\"name\", \"a.txt\""));
+    }
+
+    #[tokio::test]
+    async fn test_head_falls_back_to_get() {
+        let router = Router::new().get("/files/:name", echo_params);
+
+        let response = router.handle(request("HEAD", "/files/a.txt").await).await;
+        assert_eq!(response.status, HTTPStatus::Ok);
+        assert!(body_text(response).await.contains("\"name\", \"a.txt\""));
+
+        // an explicit HEAD route takes precedence
+        let router = router.head("/files/:name", |_| async {
+            HTTPResponse::new(HTTPStatus::NoContent)
+        });
+        let response = router.handle(request("HEAD", "/files/a.txt").await).await;
+        assert_eq!(response.status, HTTPStatus::NoContent);
+        let response = router.handle(request("PUT", "/files/a.txt").await).await;
+        assert_eq!(response.headers.get("Allow"), Some("GET, HEAD, OPTIONS"));
+    }
+
+    async fn tag(request: ParsedRequest, next: Next) -> HTTPResponse {
+        let mut response = next.run(request).await;
+        response.headers.append("X-Layer", "router");
+        response
+    }
+
+    async fn tag_route(request: ParsedRequest, next: Next) -> HTTPResponse {
+        let mut response = next.run(request).await;
+        response.headers.append("X-Layer", "route");
+        response
+    }
+
+    #[tokio::test]
+    async fn test_router_and_route_middleware() {
+        let router = Router::new()
+            .get("/plain", echo_params)
+            .get("/wrapped/:id", echo_params.layer(tag_route))
+            .layer(tag);
+
+        let response = router.handle(request("GET", "/wrapped/1").await).await;
+        assert_eq!(
+            response.headers.get_all("x-layer").collect::<Vec<_>>(),
+            vec!["route", "router"]
+        );
+        // route middleware sees the path parameters
+        assert!(body_text(response).await.contains("\"id\", \"1\""));
+
+        let response = router.handle(request("GET", "/plain").await).await;
+        assert_eq!(
+            response.headers.get_all("x-layer").collect::<Vec<_>>(),
+            vec!["router"]
+        );
+
+        // router middleware also wraps the automatic answers
+        let response = router.handle(request("GET", "/missing").await).await;
+        assert_eq!(response.status, HTTPStatus::NotFound);
+        assert_eq!(response.headers.get("x-layer"), Some("router"));
+    }
+}
@@ -0,0 +1,437 @@
+use rust_http_server::conditional::{self, Validators};
+use rust_http_server::file::{self, PathLocks};
+use rust_http_server::range;
+use rust_http_server::sandbox::Sandbox;
+use rust_http_server::Handler;
+use rust_http_server::{HTTPBody, HTTPResponse, HTTPStatus, ParsedRequest, Router, StaticFiles};
+
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::sync::OwnedMutexGuard;
+
+// Directory served under /files/ and what clients may do with it
+pub struct FileStore {
+    pub directory: PathBuf,
+    pub directory_listing: bool,
+    // create missing parent directories on upload instead of answering 409
+    pub create_directories: bool,
+    // held from checking a request's preconditions until its write is done
+    pub writers: PathLocks,
+}
+
+// Endpoints served by the binary, /files/ is only available when a directory is configured
+pub fn router(files: Option<FileStore>) -> Router {
+    let router = Router::new()
+        .get("/", index)
+        .get("/user-agent", user_agent)
+        .get("/echo/*text", echo);
+
+    let Some(files) = files else {
+        return router;
+    };
+    let static_files =
+        StaticFiles::new(&files.directory).directory_listing(files.directory_listing);
+    let store = Arc::new(files);
+    router
+        .get("/files/*path", static_files)
+        .post("/files/*path", with_store(&store, post_file))
+        .put("/files/*path", with_store(&store, put_file))
+        .patch("/files/*path", with_store(&store, patch_file))
+        .delete("/files/*path", with_store(&store, delete_file))
+}
+
+// Handler for a file endpoint that needs to know about the store
+fn with_store<F, Fut>(store: &Arc<FileStore>, handler: F) -> impl Handler
+where
+    F: Fn(Arc<FileStore>, ParsedRequest) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = HTTPResponse> + Send + 'static,
+{
+    let store = store.clone();
+    move |request| handler(store.clone(), request)
+}
+
+async fn index(_request: ParsedRequest) -> HTTPResponse {
+    HTTPResponse::new(HTTPStatus::Ok)
+}
+
+async fn user_agent(request: ParsedRequest) -> HTTPResponse {
+    HTTPResponse::builder()
+        .body(HTTPBody::text(request.headers.user_agent().unwrap_or("")))
+        .build()
+}
+
+async fn echo(request: ParsedRequest) -> HTTPResponse {
+    HTTPResponse::builder()
+        .body(HTTPBody::text(request.params.get("text").unwrap_or("")))
+        .build()
+}
+
+// File a write request targets and the validators of its current version, None if it does not
+// exist yet. Fails with the response to send when the path is invalid, names a directory or the
+// request's preconditions do not hold. Other writes of the file wait until the returned guard
+// is dropped.
+async fn write_target(
+    store: &FileStore,
+    request: &ParsedRequest,
+) -> Result<(PathBuf, Option<Validators>, OwnedMutexGuard<()>), HTTPResponse> {
+    let segments = request.params.segments("path").unwrap_or_default();
+    // same rules as for reading, so nothing can be written where it could not be read
+    let full_path = match Sandbox::new(&store.directory)
+        .resolve_segments(segments)
+        .await
+    {
+        Ok(full_path) => full_path,
+        Err(e) => return Err(HTTPResponse::new(e.status())),
+    };
+    let writing = store.writers.lock(&full_path).await;
+    let metadata = tokio::fs::metadata(&full_path).await.ok();
+    if metadata.as_ref().is_some_and(|metadata| metadata.is_dir()) {
+        return Err(HTTPResponse::new(HTTPStatus::Conflict));
+    }
+    // writes only touch the version of the file the client expects
+    let current = metadata.map(|metadata| file::validators(&metadata));
+    if let Some(response) = conditional::response_for(&request.headers, current.as_ref()) {
+        return Err(response);
+    }
+    Ok((full_path, current, writing))
+}
+
+// Response to a successful write, carrying the validators of the new version of the file
+async fn written(status: HTTPStatus, full_path: &Path) -> HTTPResponse {
+    let mut response = HTTPResponse::new(status);
+    if let Ok(metadata) = tokio::fs::metadata(full_path).await {
+        file::validators(&metadata).add_to(&mut response.headers);
+    }
+    response
+}
+
+async fn post_file(store: Arc<FileStore>, request: ParsedRequest) -> HTTPResponse {
+    let (full_path, _, _writing) = match write_target(&store, &request).await {
+        Ok(target) => target,
+        Err(response) => return response,
+    };
+
+    let body = request.body;
+    let written_bytes =
+        file::write_file_atomic(&full_path, &mut &body[..], store.create_directories).await;
+    if let Err(e) = written_bytes {
+        return HTTPResponse::new(file::write_error_status(&e));
+    }
+    let mut response = written(HTTPStatus::Created, &full_path).await;
+    response.body = Some(HTTPBody::file(body));
+    response
+}
+
+// Create or replace the file with the request body
+async fn put_file(store: Arc<FileStore>, request: ParsedRequest) -> HTTPResponse {
+    let (full_path, current, _writing) = match write_target(&store, &request).await {
+        Ok(target) => target,
+        Err(response) => return response,
+    };
+
+    let body = &mut &request.body[..];
+    if let Err(e) = file::write_file_atomic(&full_path, body, store.create_directories).await {
+        return HTTPResponse::new(file::write_error_status(&e));
+    }
+    if current.is_some() {
+        return written(HTTPStatus::NoContent, &full_path).await;
+    }
+    let mut response = written(HTTPStatus::Created, &full_path).await;
+    response.headers.insert("Location", request.headers.path());
+    response
+}
+
+// Update part of an existing file. With a Content-Range header the body replaces that range,
+// which may extend past the end of the file but not leave a gap, without one it is appended.
+// A complete length in the header has to match the length of the file after the update.
+async fn patch_file(store: Arc<FileStore>, request: ParsedRequest) -> HTTPResponse {
+    let (full_path, current, _writing) = match write_target(&store, &request).await {
+        Ok(target) => target,
+        Err(response) => return response,
+    };
+    if current.is_none() {
+        return HTTPResponse::new(HTTPStatus::NotFound);
+    }
+
+    let result = match request.headers.header("Content-Range") {
+        Some(value) => {
+            let Some((range, complete)) = range::parse_content_range(value) else {
+                return HTTPResponse::new(HTTPStatus::BadRequest);
+            };
+            if range.length() != request.body.len() as u64 {
+                return HTTPResponse::new(HTTPStatus::BadRequest);
+            }
+            let length = match tokio::fs::metadata(&full_path).await {
+                Ok(metadata) => metadata.len(),
+                Err(e) => return HTTPResponse::new(file::error_status(&e)),
+            };
+            let updated_length = length.max(range.end + 1);
+            if range.start > length || complete.is_some_and(|c| c != updated_length) {
+                return HTTPResponse::builder()
+                    .status(HTTPStatus::RangeNotSatisfiable)
+                    .header("Content-Range", &format!("bytes */{}", length))
+                    .build();
+            }
+            file::update_file_atomic(&full_path, Some(range.start), &request.body).await
+        }
+        None => file::update_file_atomic(&full_path, None, &request.body).await,
+    };
+
+    match result {
+        Ok(()) => written(HTTPStatus::NoContent, &full_path).await,
+        Err(e) => HTTPResponse::new(file::write_error_status(&e)),
+    }
+}
+
+async fn delete_file(store: Arc<FileStore>, request: ParsedRequest) -> HTTPResponse {
+    let (full_path, current, _writing) = match write_target(&store, &request).await {
+        Ok(target) => target,
+        Err(response) => return response,
+    };
+    if current.is_none() {
+        return HTTPResponse::new(HTTPStatus::NotFound);
+    }
+
+    match file::delete_file(&full_path).await {
+        Ok(()) => HTTPResponse::new(HTTPStatus::NoContent),
+        Err(e) => HTTPResponse::new(file::error_status(&e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_http_server::request::parse_stream;
+    use tempfile::TempDir;
+
+    fn store(directory: &Path, create_directories: bool) -> Option<FileStore> {
+        Some(FileStore {
+            directory: directory.to_path_buf(),
+            directory_listing: false,
+            create_directories,
+            writers: PathLocks::default(),
+        })
+    }
+
+    fn temp_root() -> TempDir {
+        let temp = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(temp.path().join("dir")).unwrap();
+        temp
+    }
+
+    async fn send(
+        router: &Router,
+        method: &str,
+        path: &str,
+        headers: &str,
+        body: &str,
+    ) -> HTTPResponse {
+        let raw = format!(
+            "{} {} HTTP/1.1\r\nContent-Length: {}\r\n{}\r\n{}",
+            method,
+            path,
+            body.len(),
+            headers,
+            body
+        );
+        router
+            .handle(parse_stream(&mut raw.as_bytes()).await.unwrap())
+            .await
+    }
+
+    fn response_etag(response: &HTTPResponse) -> String {
+        response.headers.get("ETag").unwrap().to_string()
+    }
+
+    #[tokio::test]
+    async fn test_put_creates_then_replaces() {
+        let temp = temp_root();
+        let root = temp.path();
+        let router = router(store(root, false));
+
+        let response = send(&router, "PUT", "/files/a.txt", "", "first").await;
+        assert_eq!(response.status, HTTPStatus::Created);
+        assert_eq!(response.headers.get("Location"), Some("/files/a.txt"));
+        assert!(response.headers.contains("ETag"));
+
+        let response = send(&router, "PUT", "/files/a.txt", "", "second").await;
+        assert_eq!(response.status, HTTPStatus::NoContent);
+        assert_eq!(
+            std::fs::read_to_string(root.join("a.txt")).unwrap(),
+            "second"
+        );
+
+        let response = send(&router, "PUT", "/files/dir", "", "x").await;
+        assert_eq!(response.status, HTTPStatus::Conflict);
+
+        let response = send(&router, "PUT", "/files/a.txt", "If-None-Match: *\r\n", "x").await;
+        assert_eq!(response.status, HTTPStatus::PreconditionFailed);
+
+        // the tag of each write is good for the next one right away, and only for that one
+        let etag = response_etag(&send(&router, "PUT", "/files/a.txt", "", "third").await);
+        let if_match = format!("If-Match: {}\r\n", etag);
+        let response = send(&router, "PUT", "/files/a.txt", &if_match, "fourth").await;
+        assert_eq!(response.status, HTTPStatus::NoContent);
+        let next = response_etag(&response);
+        let response = send(&router, "PUT", "/files/a.txt", &if_match, "fifth").await;
+        assert_eq!(response.status, HTTPStatus::PreconditionFailed);
+        let if_match = format!("If-Match: {}\r\n", next);
+        let response = send(&router, "PATCH", "/files/a.txt", &if_match, "!").await;
+        assert_eq!(response.status, HTTPStatus::NoContent);
+        assert_eq!(
+            std::fs::read_to_string(root.join("a.txt")).unwrap(),
+            "fourth!"
+        );
+
+        let response = send(&router, "PUT", "/files/..%2fescape.txt", "", "x").await;
+        assert_eq!(response.status, HTTPStatus::BadRequest);
+        let response = send(&router, "PUT", "/files/.env", "", "x").await;
+        assert_eq!(response.status, HTTPStatus::NotFound);
+        assert!(!root.join(".env").exists());
+    }
+
+    #[tokio::test]
+    async fn test_put_parent_directories() {
+        let temp = temp_root();
+        let root = temp.path();
+
+        let strict = router(store(root, false));
+        let response = send(&strict, "PUT", "/files/new/a.txt", "", "data").await;
+        assert_eq!(response.status, HTTPStatus::Conflict);
+        assert!(!root.join("new").exists());
+
+        let creating = router(store(root, true));
+        let response = send(&creating, "PUT", "/files/new/a.txt", "", "data").await;
+        assert_eq!(response.status, HTTPStatus::Created);
+        assert_eq!(
+            std::fs::read_to_string(root.join("new/a.txt")).unwrap(),
+            "data"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_delete() {
+        let temp = temp_root();
+        let root = temp.path();
+        std::fs::write(root.join("a.txt"), "data").unwrap();
+        let router = router(store(root, false));
+
+        let response = send(&router, "DELETE", "/files/a.txt", "", "").await;
+        assert_eq!(response.status, HTTPStatus::NoContent);
+        assert!(!root.join("a.txt").exists());
+
+        let response = send(&router, "DELETE", "/files/a.txt", "", "").await;
+        assert_eq!(response.status, HTTPStatus::NotFound);
+    }
+
+    #[tokio::test]
+    async fn test_patch_appends_and_updates_ranges() {
+        let temp = temp_root();
+        let root = temp.path();
+        std::fs::write(root.join("a.txt"), "hello").unwrap();
+        let router = router(store(root, false));
+        let read = || std::fs::read_to_string(root.join("a.txt")).unwrap();
+
+        let response = send(&router, "PATCH", "/files/a.txt", "", " world").await;
+        assert_eq!(response.status, HTTPStatus::NoContent);
+        assert_eq!(read(), "hello world");
+
+        let headers = "Content-Range: bytes 0-4/*\r\n";
+        send(&router, "PATCH", "/files/a.txt", headers, "HELLO").await;
+        assert_eq!(read(), "HELLO world");
+
+        // writing right at the end extends the file
+        let headers = "Content-Range: bytes 11-11/*\r\n";
+        send(&router, "PATCH", "/files/a.txt", headers, "!").await;
+        assert_eq!(read(), "HELLO world!");
+
+        let headers = "Content-Range: bytes 20-20/*\r\n";
+        let response = send(&router, "PATCH", "/files/a.txt", headers, "x").await;
+        assert_eq!(response.status, HTTPStatus::RangeNotSatisfiable);
+
+        // the complete length has to agree with the file
+        let headers = "Content-Range: bytes 0-4/20\r\n";
+        let response = send(&router, "PATCH", "/files/a.txt", headers, "hello").await;
+        assert_eq!(response.status, HTTPStatus::RangeNotSatisfiable);
+        assert_eq!(response.headers.get("Content-Range"), Some("bytes */12"));
+        let headers = "Content-Range: bytes 0-4/12\r\n";
+        let response = send(&router, "PATCH", "/files/a.txt", headers, "Hello").await;
+        assert_eq!(response.status, HTTPStatus::NoContent);
+        let headers = "Content-Range: bytes 12-12/13\r\n";
+        let response = send(&router, "PATCH", "/files/a.txt", headers, "?").await;
+        assert_eq!(response.status, HTTPStatus::NoContent);
+        assert_eq!(read(), "Hello world!?");
+        std::fs::write(root.join("a.txt"), "HELLO world!").unwrap();
+
+        let headers = "Content-Range: bytes 0-9/*\r\n";
+        let response = send(&router, "PATCH", "/files/a.txt", headers, "short").await;
+        assert_eq!(response.status, HTTPStatus::BadRequest);
+        // a span whose length overflows is not mistaken for an empty one
+        let headers = "Content-Range: bytes 0-18446744073709551615/*\r\n";
+        let response = send(&router, "PATCH", "/files/a.txt", headers, "").await;
+        assert_eq!(response.status, HTTPStatus::BadRequest);
+        assert_eq!(read(), "HELLO world!");
+
+        let response = send(&router, "PATCH", "/files/missing.txt", "", "x").await;
+        assert_eq!(response.status, HTTPStatus::NotFound);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_concurrent_appends_are_not_lost() {
+        let temp = temp_root();
+        let root = temp.path();
+        std::fs::write(root.join("a.txt"), "").unwrap();
+        let router = Arc::new(router(store(root, false)));
+
+        let appends: Vec<_> = (0..20)
+            .map(|_| {
+                let router = router.clone();
+                tokio::spawn(
+                    async move { send(&router, "PATCH", "/files/a.txt", "", "x").await.status },
+                )
+            })
+            .collect();
+        for append in appends {
+            assert_eq!(append.await.unwrap(), HTTPStatus::NoContent);
+        }
+        assert_eq!(
+            std::fs::read_to_string(root.join("a.txt")).unwrap(),
+            "x".repeat(20)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_echo_and_query_strings() {
+        let temp = temp_root();
+        let root = temp.path();
+        std::fs::write(root.join("a b.txt"), "data").unwrap();
+        let router = router(store(root, false));
+
+        let response = send(&router, "GET", "/echo/hello%20world", "", "").await;
+        assert!(String::from_utf8(response.to_bytes())
+            .unwrap()
+            .ends_with("\r\n\r\nhello world"));
+
+        let response = send(&router, "GET", "/files/a%20b.txt?download=1", "", "").await;
+        assert_eq!(response.status, HTTPStatus::Ok);
+
+        let response = send(&router, "PUT", "/files/new.txt?v=2", "", "x").await;
+        assert_eq!(response.status, HTTPStatus::Created);
+        assert_eq!(response.headers.get("Location"), Some("/files/new.txt"));
+        assert!(root.join("new.txt").exists());
+    }
+
+    #[tokio::test]
+    async fn test_unsupported_method() {
+        let temp = temp_root();
+        let root = temp.path();
+        let router = router(store(root, false));
+        let response = send(&router, "TRACE", "/files/a.txt", "", "").await;
+        assert_eq!(response.status, HTTPStatus::MethodNotAllowed);
+        assert_eq!(
+            response.headers.get("Allow"),
+            Some("GET, HEAD, POST, PUT, PATCH, DELETE, OPTIONS")
+        );
+    }
+}
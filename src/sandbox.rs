@@ -0,0 +1,391 @@
+use crate::file;
+use crate::http::{percent_decode, HTTPStatus};
+
+use std::io::{self, ErrorKind};
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+// What to do with symbolic links met while resolving a path
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SymlinkPolicy {
+    // refuse any path that runs through a link
+    Deny,
+    // follow links whose target is inside the root
+    #[default]
+    WithinRoot,
+    // follow every link, the root only limits the names clients can use
+    Follow,
+}
+
+// What to do with names starting with a dot, such as .git or .env
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DotfilePolicy {
+    // answer as if they did not exist
+    #[default]
+    Hide,
+    Allow,
+}
+
+#[derive(Debug, Error)]
+pub enum ResolveError {
+    #[error("malformed path")]
+    InvalidPath,
+    #[error("path leaves the root directory")]
+    Traversal,
+    #[error("path runs through a forbidden symbolic link")]
+    Symlink,
+    #[error("path names a hidden file")]
+    Hidden,
+    #[error(transparent)]
+    Io(#[from] io::Error),
+}
+
+impl ResolveError {
+    // Status to answer the request with, hidden files are reported as missing
+    pub fn status(&self) -> HTTPStatus {
+        match self {
+            ResolveError::InvalidPath | ResolveError::Traversal => HTTPStatus::BadRequest,
+            ResolveError::Symlink => HTTPStatus::Forbidden,
+            ResolveError::Hidden => HTTPStatus::NotFound,
+            ResolveError::Io(e) => file::error_status(e),
+        }
+    }
+}
+
+// Maps request paths to files below a root directory so that no request can reach outside of it,
+// e.g. through `..`, encoded separators or symbolic links.
+#[derive(Debug, Clone)]
+pub struct Sandbox {
+    root: PathBuf,
+    symlinks: SymlinkPolicy,
+    dotfiles: DotfilePolicy,
+}
+
+impl Sandbox {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Sandbox {
+            root: root.into(),
+            symlinks: SymlinkPolicy::default(),
+            dotfiles: DotfilePolicy::default(),
+        }
+    }
+
+    pub fn symlinks(mut self, policy: SymlinkPolicy) -> Self {
+        self.symlinks = policy;
+        self
+    }
+
+    pub fn dotfiles(mut self, policy: DotfilePolicy) -> Self {
+        self.dotfiles = policy;
+        self
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    // Whether an entry of this name is kept from clients
+    pub fn is_hidden(&self, name: &str) -> bool {
+        self.dotfiles == DotfilePolicy::Hide && name.starts_with('.')
+    }
+
+    // Resolve a still percent-encoded path relative to the root, see `resolve_segments`
+    pub async fn resolve(&self, encoded: &str) -> Result<PathBuf, ResolveError> {
+        let segments = decode_segments(encoded)?;
+        self.resolve_segments(segments.iter().map(String::as_str))
+            .await
+    }
+
+    // Resolve decoded path segments relative to the root. Empty and `.` segments are skipped
+    // and `..` removes the previous segment but may not climb above the root. The result is
+    // canonical as far as it exists, the rest is left to be created by the caller.
+    pub async fn resolve_segments<'a, I>(&self, segments: I) -> Result<PathBuf, ResolveError>
+    where
+        I: IntoIterator<Item = &'a str>,
+    {
+        let mut normalized: Vec<&str> = Vec::new();
+        for segment in segments {
+            match segment {
+                "" | "." => {}
+                ".." => {
+                    normalized.pop().ok_or(ResolveError::Traversal)?;
+                }
+                // a segment may hold a slash that was sent encoded, backslashes are separators on
+                // Windows and colons start alternate data streams
+                _ if segment.contains(['\0', '/', '\\'])
+                    || (cfg!(windows) && segment.contains(':')) =>
+                {
+                    return Err(ResolveError::InvalidPath)
+                }
+                _ => normalized.push(segment),
+            }
+        }
+        if normalized.iter().any(|segment| self.is_hidden(segment)) {
+            return Err(ResolveError::Hidden);
+        }
+
+        let root = tokio::fs::canonicalize(&self.root).await?;
+        let mut resolved = root.clone();
+        let mut remaining = normalized.into_iter();
+        while let Some(segment) = remaining.next() {
+            let next = resolved.join(segment);
+            let metadata = match tokio::fs::symlink_metadata(&next).await {
+                Ok(metadata) => metadata,
+                Err(e) if e.kind() == ErrorKind::NotFound => {
+                    // nothing below a missing entry can be a link
+                    resolved = next;
+                    resolved.extend(remaining);
+                    return Ok(resolved);
+                }
+                Err(e) => return Err(e.into()),
+            };
+            if !metadata.file_type().is_symlink() {
+                resolved = next;
+                continue;
+            }
+
+            if self.symlinks == SymlinkPolicy::Deny {
+                return Err(ResolveError::Symlink);
+            }
+            let target = tokio::fs::canonicalize(&next).await?;
+            if self.symlinks == SymlinkPolicy::WithinRoot && !target.starts_with(&root) {
+                return Err(ResolveError::Symlink);
+            }
+            resolved = target;
+        }
+        Ok(resolved)
+    }
+}
+
+// Split a percent-encoded path into decoded segments, escapes have to decode to UTF-8
+fn decode_segments(encoded: &str) -> Result<Vec<String>, ResolveError> {
+    encoded
+        .split('/')
+        .map(|segment| {
+            let decoded = percent_decode(segment).ok_or(ResolveError::InvalidPath)?;
+            String::from_utf8(decoded).map_err(|_| ResolveError::InvalidPath)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    // Layout used by the attack tests:
+    //   <base>/root/public/a.txt
+    //   <base>/root/.env
+    //   <base>/root/public/.git/config
+    //   <base>/outside/secret.txt
+    // plus, on unix, links from inside the root to both places
+    fn layout() -> TempDir {
+        let temp = tempfile::tempdir().unwrap();
+        let base = temp.path();
+        std::fs::create_dir_all(base.join("root/public/.git")).unwrap();
+        std::fs::create_dir_all(base.join("outside")).unwrap();
+        std::fs::write(base.join("root/public/a.txt"), "public").unwrap();
+        std::fs::write(base.join("root/.env"), "SECRET=1").unwrap();
+        std::fs::write(base.join("root/public/.git/config"), "[core]").unwrap();
+        std::fs::write(base.join("outside/secret.txt"), "secret").unwrap();
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::symlink;
+            symlink(base.join("outside"), base.join("root/escape")).unwrap();
+            symlink(
+                base.join("outside/secret.txt"),
+                base.join("root/secret.txt"),
+            )
+            .unwrap();
+            symlink(base.join("root/public"), base.join("root/alias")).unwrap();
+            symlink("public/a.txt", base.join("root/relative.txt")).unwrap();
+            symlink(base.join("root/missing"), base.join("root/dangling")).unwrap();
+        }
+        temp
+    }
+
+    fn canonical(base: &Path, path: &str) -> PathBuf {
+        std::fs::canonicalize(base).unwrap().join(path)
+    }
+
+    #[tokio::test]
+    async fn test_resolves_paths_inside_root() {
+        let temp = layout();
+        let base = temp.path();
+        let sandbox = Sandbox::new(base.join("root"));
+
+        let expected = canonical(base, "root/public/a.txt");
+        for path in [
+            "public/a.txt",
+            "/public/a.txt",
+            "public//a.txt",
+            "./public/./a.txt",
+            "public/x/../a.txt",
+            "public/%61.txt",
+            "%70ublic/a.txt",
+        ] {
+            assert_eq!(sandbox.resolve(path).await.unwrap(), expected, "{}", path);
+        }
+
+        assert_eq!(sandbox.resolve("").await.unwrap(), canonical(base, "root"));
+        // missing files resolve so they can be created
+        assert_eq!(
+            sandbox.resolve("new/dir/b.txt").await.unwrap(),
+            canonical(base, "root/new/dir/b.txt")
+        );
+        assert_eq!(
+            sandbox.resolve("public/a%20b.txt").await.unwrap(),
+            canonical(base, "root/public/a b.txt")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_rejects_traversal() {
+        let temp = layout();
+        let base = temp.path();
+        let sandbox = Sandbox::new(base.join("root"));
+
+        for path in [
+            "..",
+            "../outside/secret.txt",
+            "public/../../outside/secret.txt",
+            "public/../..",
+            "%2e%2e/outside/secret.txt",
+            "%2E%2E/outside/secret.txt",
+            ".%2e/outside/secret.txt",
+            "public/%2e%2e/%2e%2e/outside/secret.txt",
+        ] {
+            let error = sandbox.resolve(path).await.unwrap_err();
+            assert!(
+                matches!(error, ResolveError::Traversal),
+                "{}: {:?}",
+                path,
+                error
+            );
+            assert_eq!(error.status(), HTTPStatus::BadRequest);
+        }
+
+        for path in [
+            "..%2foutside/secret.txt",
+            "..%2Foutside%2Fsecret.txt",
+            "public%2f..%2f..%2foutside",
+            "..\\outside\\secret.txt",
+            "%2e%2e%5coutside%5csecret.txt",
+            "public/a.txt%00.png",
+            "%",
+            "%zz/a.txt",
+            // overlong UTF-8 encoding of '.'
+            "%c0%ae%c0%ae/outside",
+        ] {
+            let error = sandbox.resolve(path).await.unwrap_err();
+            assert!(
+                matches!(error, ResolveError::InvalidPath),
+                "{}: {:?}",
+                path,
+                error
+            );
+        }
+
+        // double encoding is decoded once, leaving a literal and harmless name
+        assert_eq!(
+            sandbox.resolve("%252e%252e/outside").await.unwrap(),
+            canonical(base, "root/%2e%2e/outside")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_dotfiles() {
+        let temp = layout();
+        let base = temp.path();
+        let sandbox = Sandbox::new(base.join("root"));
+
+        for path in [".env", "public/.git/config", "%2eenv", "public/.git"] {
+            let error = sandbox.resolve(path).await.unwrap_err();
+            assert!(
+                matches!(error, ResolveError::Hidden),
+                "{}: {:?}",
+                path,
+                error
+            );
+            assert_eq!(error.status(), HTTPStatus::NotFound);
+        }
+
+        let sandbox = sandbox.dotfiles(DotfilePolicy::Allow);
+        assert_eq!(
+            sandbox.resolve(".env").await.unwrap(),
+            canonical(base, "root/.env")
+        );
+        assert_eq!(
+            sandbox.resolve("public/.git/config").await.unwrap(),
+            canonical(base, "root/public/.git/config")
+        );
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_symlinks_within_root() {
+        let temp = layout();
+        let base = temp.path();
+        let sandbox = Sandbox::new(base.join("root"));
+
+        let public = canonical(base, "root/public/a.txt");
+        assert_eq!(sandbox.resolve("alias/a.txt").await.unwrap(), public);
+        assert_eq!(sandbox.resolve("relative.txt").await.unwrap(), public);
+
+        for path in [
+            "secret.txt",
+            "escape/secret.txt",
+            "escape",
+            "escape/new.txt",
+        ] {
+            let error = sandbox.resolve(path).await.unwrap_err();
+            assert!(
+                matches!(error, ResolveError::Symlink),
+                "{}: {:?}",
+                path,
+                error
+            );
+            assert_eq!(error.status(), HTTPStatus::Forbidden);
+        }
+
+        // a link to nowhere cannot be checked and is not followed
+        let error = sandbox.resolve("dangling").await.unwrap_err();
+        assert_eq!(error.status(), HTTPStatus::NotFound);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_symlink_policies() {
+        let temp = layout();
+        let base = temp.path();
+
+        let deny = Sandbox::new(base.join("root")).symlinks(SymlinkPolicy::Deny);
+        for path in ["alias/a.txt", "relative.txt", "escape/secret.txt"] {
+            let error = deny.resolve(path).await.unwrap_err();
+            assert!(
+                matches!(error, ResolveError::Symlink),
+                "{}: {:?}",
+                path,
+                error
+            );
+        }
+        assert!(deny.resolve("public/a.txt").await.is_ok());
+
+        let follow = Sandbox::new(base.join("root")).symlinks(SymlinkPolicy::Follow);
+        assert_eq!(
+            follow.resolve("escape/secret.txt").await.unwrap(),
+            canonical(base, "outside/secret.txt")
+        );
+        // following links does not allow `..` past the root
+        let error = follow.resolve("escape/../../outside").await.unwrap_err();
+        assert!(matches!(error, ResolveError::Traversal));
+    }
+
+    #[tokio::test]
+    async fn test_missing_root() {
+        let sandbox = Sandbox::new("/definitely/not/a/directory");
+        let error = sandbox.resolve("a.txt").await.unwrap_err();
+        assert_eq!(error.status(), HTTPStatus::NotFound);
+    }
+}
@@ -1,111 +1,446 @@
-use crate::{config::Settings, connection::handle_connection, shutdown::ShutdownSignal};
-use std::io::{self};
+use crate::{
+    config::Settings,
+    connection::handle_connection,
+    log,
+    router::Router,
+    shutdown::{Draining, ShutdownSignal},
+};
+use std::fmt;
+use std::future::{self, Future};
+use std::io::{self, ErrorKind};
+use std::net::SocketAddr;
+use std::pin::Pin;
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::net::{TcpListener, TcpStream};
-use tokio::sync::mpsc;
+use tokio::sync::{mpsc, watch};
+use tokio::task::JoinSet;
+use tokio::time::{self, Instant};
+
+type ShutdownFuture = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+// Exit codes for a server that cannot start, as in sysexits.h
+pub const EXIT_IO_ERROR: i32 = 74;
+pub const EXIT_CONFIG_ERROR: i32 = 78;
+// Exit code once a running server's listener keeps failing
+pub const EXIT_LISTENER_FAILED: i32 = 1;
+
+// Accept errors in a row after which the listener is considered broken
+const MAX_ACCEPT_FAILURES: u32 = 50;
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(100);
 
 pub struct Server {
     settings: Arc<Settings>,
-    listener: TcpListener,
-    rx: mpsc::Receiver<ShutdownSignal>,
+    router: Arc<Router>,
+    // None once the server stopped accepting connections
+    listener: Option<TcpListener>,
+    rx: Option<mpsc::Receiver<ShutdownSignal>>,
+    shutdown: ShutdownFuture,
+    // tasks of the connections that are still open
+    connections: JoinSet<()>,
+    start_draining: watch::Sender<bool>,
+    draining: Draining,
+    accept_failures: u32,
+    started: Instant,
+    accepted: u64,
 }
 
-impl Server {
-    pub async fn new(
-        settings: Arc<Settings>,
-        rx: mpsc::Receiver<ShutdownSignal>,
-    ) -> io::Result<Self> {
-        let address = format!("{}:{}", settings.hostname, settings.port);
+// Snapshot of a running server, printed on SIGUSR2
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServerStatus {
+    // None once the server stopped listening
+    pub address: Option<SocketAddr>,
+    pub uptime: Duration,
+    pub open_connections: usize,
+    pub accepted_connections: u64,
+    pub draining: bool,
+}
+
+impl fmt::Display for ServerStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.address {
+            Some(address) => write!(f, "listening on {}", address)?,
+            None => write!(f, "not listening")?,
+        }
+        write!(
+            f,
+            ", up {}s, {} open connection(s), {} accepted",
+            self.uptime.as_secs(),
+            self.open_connections,
+            self.accepted_connections
+        )?;
+        if self.draining {
+            write!(f, ", draining")?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExitReason {
+    // a NormalExit signal or the shutdown future
+    Shutdown,
+    // every sender of the signal channel is gone
+    SignalsClosed,
+    // an ErrorExit signal, sent from outside or raised by the server on a fatal error
+    Error(i32),
+}
+
+impl ExitReason {
+    // Process exit code, 0 unless the server stopped with an error
+    pub fn code(&self) -> i32 {
+        match self {
+            ExitReason::Error(code) => *code,
+            ExitReason::Shutdown | ExitReason::SignalsClosed => 0,
+        }
+    }
+}
+
+// How `Server::run` ended, including what happened to the connections open at that point
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ServerExit {
+    pub reason: ExitReason,
+    pub code: i32,
+    // closed after finishing their request in progress
+    pub drained: usize,
+    // still open at the drain deadline and closed forcibly
+    pub aborted: usize,
+}
+
+impl ServerExit {
+    pub fn is_success(&self) -> bool {
+        self.code == 0
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct DrainReport {
+    drained: usize,
+    aborted: usize,
+}
+
+// Programmatic setup of a server, e.g.
+// `Server::builder().settings(settings).router(router).shutdown(stop).bind().await?`
+// Everything is optional, the defaults listen on the address from `Settings::default()`,
+// answer every request with 404 and run until the process is stopped.
+pub struct ServerBuilder {
+    settings: Arc<Settings>,
+    router: Router,
+    rx: Option<mpsc::Receiver<ShutdownSignal>>,
+    shutdown: ShutdownFuture,
+}
+
+impl ServerBuilder {
+    pub fn settings(mut self, settings: impl Into<Arc<Settings>>) -> Self {
+        self.settings = settings.into();
+        self
+    }
+
+    pub fn router(mut self, router: Router) -> Self {
+        self.router = router;
+        self
+    }
+
+    // Channel of shutdown and reload signals, see `shutdown::handle_shutdown_signals`
+    pub fn signals(mut self, rx: mpsc::Receiver<ShutdownSignal>) -> Self {
+        self.rx = Some(rx);
+        self
+    }
+
+    // The server exits normally once `shutdown` completes
+    pub fn shutdown<F>(mut self, shutdown: F) -> Self
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        self.shutdown = Box::pin(shutdown);
+        self
+    }
+
+    pub async fn bind(self) -> io::Result<Server> {
+        let address = format!("{}:{}", self.settings.hostname, self.settings.port);
         let listener = TcpListener::bind(&address).await?;
-        println!("Server listening on {}", address);
+        log::write(&format!("Server listening on {}", listener.local_addr()?));
 
+        let (start_draining, draining) = Draining::channel();
         Ok(Server {
-            settings,
-            listener,
-            rx,
+            settings: self.settings,
+            router: Arc::new(self.router),
+            listener: Some(listener),
+            rx: self.rx,
+            shutdown: self.shutdown,
+            connections: JoinSet::new(),
+            start_draining,
+            draining,
+            accept_failures: 0,
+            started: Instant::now(),
+            accepted: 0,
         })
     }
+}
+
+impl Server {
+    pub fn builder() -> ServerBuilder {
+        ServerBuilder {
+            settings: Arc::new(Settings::default()),
+            router: Router::new(),
+            rx: None,
+            shutdown: Box::pin(future::pending()),
+        }
+    }
 
-    pub async fn run(&mut self) -> Option<i32> {
-        let exit_code: Option<i32> = None;
+    // Address the listener is bound to, useful when the configured port is 0
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        match &self.listener {
+            Some(listener) => listener.local_addr(),
+            None => Err(io::Error::new(
+                ErrorKind::NotConnected,
+                "server stopped listening",
+            )),
+        }
+    }
 
-        loop {
+    // Serve connections until a shutdown signal arrives or the listener fails, then drain the
+    // open ones
+    pub async fn run(&mut self) -> ServerExit {
+        let reason = loop {
             // Simultaneously listen for TCP connections and shutdown signals sent via channel
             tokio::select! {
-                accept_result = self.listener.accept() => {
-                    if let Ok((socket, _)) = accept_result {
-                        self.handle_incoming_connection(socket).await;
+                accept_result = accept(&self.listener) => match accept_result {
+                    Ok((socket, _)) => {
+                        self.accept_failures = 0;
+                        self.handle_incoming_connection(socket);
                     }
-                }
-                shutdown_signal = self.rx.recv() => {
-                    if self.process_shutdown_signal(shutdown_signal).await {
-                        break;
+                    Err(e) => {
+                        if let Some(reason) = self.accept_failed(e).await {
+                            break reason;
+                        }
                     }
+                },
+                // finished connections are collected as they go, so only live ones are kept
+                Some(_) = self.connections.join_next(), if !self.connections.is_empty() => {}
+                shutdown_signal = next_signal(&mut self.rx) => {
+                    if let Some(reason) = self.process_shutdown_signal(shutdown_signal).await {
+                        break reason;
+                    }
+                }
+                _ = &mut self.shutdown => {
+                    log::write("Shutting down normally.");
+                    break ExitReason::Shutdown;
                 }
             }
+        };
+
+        let report = self.drain().await;
+        log::write(&format!(
+            "Closed {} connection(s) after their last request, aborted {}",
+            report.drained, report.aborted
+        ));
+        ServerExit {
+            reason,
+            code: reason.code(),
+            drained: report.drained,
+            aborted: report.aborted,
         }
+    }
 
-        exit_code
+    // Errors about a single connection are skipped, anything else is retried after a pause
+    // (e.g. running out of file descriptors) until it has failed too often in a row
+    async fn accept_failed(&mut self, e: io::Error) -> Option<ExitReason> {
+        if matches!(
+            e.kind(),
+            ErrorKind::ConnectionAborted
+                | ErrorKind::ConnectionReset
+                | ErrorKind::ConnectionRefused
+                | ErrorKind::Interrupted
+        ) {
+            return None;
+        }
+        log::error(&format!("Failed to accept connection: {}", e));
+        self.accept_failures += 1;
+        if self.accept_failures >= MAX_ACCEPT_FAILURES {
+            log::error(&format!(
+                "Listener failed {} times in a row",
+                self.accept_failures
+            ));
+            return self
+                .process_shutdown_signal(Some(ShutdownSignal::ErrorExit(EXIT_LISTENER_FAILED)))
+                .await;
+        }
+        time::sleep(ACCEPT_BACKOFF).await;
+        None
     }
 
-    async fn handle_incoming_connection(&self, socket: TcpStream) {
+    pub fn status(&self) -> ServerStatus {
+        ServerStatus {
+            address: self.local_addr().ok(),
+            uptime: self.started.elapsed(),
+            open_connections: self.connections.len(),
+            accepted_connections: self.accepted,
+            draining: *self.start_draining.borrow(),
+        }
+    }
+
+    fn handle_incoming_connection(&mut self, socket: TcpStream) {
+        self.accepted += 1;
         let settings_clone = self.settings.clone();
-        tokio::spawn(async move {
-            if let Err(e) = handle_connection(socket, settings_clone).await {
-                eprintln!("Failed to handle connection: {}", e);
+        let router_clone = self.router.clone();
+        let draining = self.draining.clone();
+        self.connections.spawn(async move {
+            if let Err(e) = handle_connection(socket, settings_clone, router_clone, draining).await
+            {
+                log::error(&format!("Failed to handle connection: {}", e));
             }
         });
     }
 
-    async fn process_shutdown_signal(&mut self, shutdown_signal: Option<ShutdownSignal>) -> bool {
+    // Stop accepting and ask every open connection to close once its request in progress is
+    // answered. Connections still open after `drain_timeout`, or when another shutdown signal
+    // arrives in the meantime, are aborted.
+    async fn drain(&mut self) -> DrainReport {
+        self.listener = None;
+        if !self.connections.is_empty() {
+            log::write(&format!(
+                "Draining {} connection(s)",
+                self.connections.len()
+            ));
+        }
+        self.start_draining.send_replace(true);
+
+        let deadline = Instant::now() + self.settings.drain_timeout;
+        let mut drained = 0;
+        loop {
+            tokio::select! {
+                joined = time::timeout_at(deadline, self.connections.join_next()) => match joined {
+                    Ok(Some(_)) => drained += 1,
+                    // every connection is closed or the deadline passed
+                    Ok(None) | Err(_) => break,
+                },
+                shutdown_signal = next_signal(&mut self.rx) => match shutdown_signal {
+                    Some(ShutdownSignal::NormalExit | ShutdownSignal::ErrorExit(_)) => {
+                        log::write("Shutdown signal received again, closing connections now.");
+                        break;
+                    }
+                    Some(ShutdownSignal::ReloadConfig) => {
+                        log::write("Not reloading configuration while shutting down.");
+                    }
+                    Some(shutdown_signal) => {
+                        self.process_shutdown_signal(Some(shutdown_signal)).await;
+                    }
+                    // nothing can interrupt the drain anymore
+                    None => self.rx = None,
+                },
+            }
+        }
+        let aborted = self.connections.len();
+        self.connections.shutdown().await;
+        DrainReport { drained, aborted }
+    }
+
+    // Why the server should stop, None to keep running
+    async fn process_shutdown_signal(
+        &mut self,
+        shutdown_signal: Option<ShutdownSignal>,
+    ) -> Option<ExitReason> {
         match shutdown_signal {
             Some(ShutdownSignal::NormalExit) => {
-                println!("Shutting down normally.");
-                true
+                log::write("Shutting down normally.");
+                Some(ExitReason::Shutdown)
             }
             Some(ShutdownSignal::ErrorExit(code)) => {
-                eprintln!("Shutting down with error code: {}", code);
-                true
+                log::error(&format!("Shutting down with error code: {}", code));
+                Some(ExitReason::Error(code))
             }
+            // a reload that fails leaves the running server as it was
             Some(ShutdownSignal::ReloadConfig) => {
-                println!("Reloading configuration.");
-                let new_settings: Arc<Settings> = Arc::new(
-                    Settings::load()
-                        .await
-                        .expect("Failed to reload settings!"),
-                );
-                self.reload_server(new_settings).await;
-                false // Indicates not to exit
+                log::write("Reloading configuration.");
+                match Settings::load().await {
+                    Ok(settings) => {
+                        if let Err(e) = self.reload_server(Arc::new(settings)).await {
+                            log::error(&format!(
+                                "Reload failed, still listening on the old address: {}",
+                                e
+                            ));
+                        }
+                    }
+                    Err(e) => {
+                        log::error(&format!("Reload failed, keeping the old settings: {}", e));
+                    }
+                }
+                None
+            }
+            Some(ShutdownSignal::ReopenLogs) => {
+                match log::reopen().await {
+                    Ok(Some(path)) => log::write(&format!("Reopened log file {}", path.display())),
+                    Ok(None) => log::write("Logging to stdout, no log file to reopen."),
+                    Err(e) => log::error(&format!("Failed to reopen log file: {}", e)),
+                }
+                None
             }
-            None => true, // Channel closed
+            Some(ShutdownSignal::DumpStatus) => {
+                log::write(&format!("Status: {}", self.status()));
+                None
+            }
+            None => Some(ExitReason::SignalsClosed),
         }
     }
 
-    pub async fn reload_server(&mut self, new_settings: Arc<Settings>) {
-        println!("Server reload triggered!");
+    // Apply new settings, the listener is only replaced when the address changed since the old
+    // one would still hold the port. Nothing changes when the new address cannot be bound.
+    pub async fn reload_server(&mut self, new_settings: Arc<Settings>) -> io::Result<()> {
+        log::write("Server reload triggered!");
+        let address = format!("{}:{}", new_settings.hostname, new_settings.port);
+        let current = format!("{}:{}", self.settings.hostname, self.settings.port);
+        if address != current || self.listener.is_none() {
+            self.listener = Some(TcpListener::bind(&address).await?);
+        }
         self.settings = new_settings;
-        let address = format!("{}:{}", self.settings.hostname, self.settings.port);
-        self.listener = TcpListener::bind(&address).await.unwrap();
-        println!("Server reinitialized successfully, now listening on {}", address);
+        log::write(&format!(
+            "Server reinitialized successfully, now listening on {}",
+            address
+        ));
+        Ok(())
+    }
+}
+
+async fn accept(listener: &Option<TcpListener>) -> io::Result<(TcpStream, SocketAddr)> {
+    match listener {
+        Some(listener) => listener.accept().await,
+        None => future::pending().await,
+    }
+}
+
+// Next signal from the channel, a server without one only stops through its shutdown future
+async fn next_signal(rx: &mut Option<mpsc::Receiver<ShutdownSignal>>) -> Option<ShutdownSignal> {
+    match rx {
+        Some(rx) => rx.recv().await,
+        None => future::pending().await,
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::{HTTPBody, HTTPResponse, ParsedRequest};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::sync::oneshot;
 
     #[tokio::test]
     async fn test_server_new() {
         let hostname: String = "127.0.0.1".to_string();
         let port: String = "0".to_string();
         let settings = Arc::new(Settings {
-            hostname: hostname,
-            port: port,
+            hostname,
+            port,
             buffer_size: 1024,
+            ..Settings::default()
         });
         let (_tx, rx) = mpsc::channel(1); // Create a mock channel
 
-        let server = Server::new(settings, rx).await;
+        let server = Server::builder()
+            .settings(settings)
+            .signals(rx)
+            .bind()
+            .await;
         assert!(server.is_ok());
     }
 
@@ -114,18 +449,25 @@ mod tests {
         let hostname: String = "127.0.0.1".to_string();
         let port: String = "0".to_string();
         let settings = Arc::new(Settings {
-            hostname: hostname,
-            port: port,
+            hostname,
+            port,
             buffer_size: 1024,
+            ..Settings::default()
         });
         let (tx, rx) = mpsc::channel(1);
-        let mut server = Server::new(settings, rx).await.unwrap();
+        let mut server = Server::builder()
+            .settings(settings)
+            .signals(rx)
+            .bind()
+            .await
+            .unwrap();
 
         tx.send(ShutdownSignal::NormalExit).await.unwrap();
-        assert!(
+        assert_eq!(
             server
                 .process_shutdown_signal(Some(ShutdownSignal::NormalExit))
-                .await
+                .await,
+            Some(ExitReason::Shutdown)
         );
     }
 
@@ -134,18 +476,25 @@ mod tests {
         let hostname: String = "127.0.0.1".to_string();
         let port: String = "0".to_string();
         let settings = Arc::new(Settings {
-            hostname: hostname,
-            port: port,
+            hostname,
+            port,
             buffer_size: 1024,
+            ..Settings::default()
         });
         let (tx, rx) = mpsc::channel(1);
-        let mut server = Server::new(settings, rx).await.unwrap();
+        let mut server = Server::builder()
+            .settings(settings)
+            .signals(rx)
+            .bind()
+            .await
+            .unwrap();
 
         tx.send(ShutdownSignal::ErrorExit(1)).await.unwrap();
-        assert!(
+        assert_eq!(
             server
                 .process_shutdown_signal(Some(ShutdownSignal::ErrorExit(1)))
-                .await
+                .await,
+            Some(ExitReason::Error(1))
         );
     }
 
@@ -155,26 +504,300 @@ mod tests {
             hostname: "127.0.0.1".to_string(),
             port: "0".to_string(),
             buffer_size: 1024,
+            ..Settings::default()
         });
         let (tx, rx) = mpsc::channel(1);
-        let mut server = Server::new(initial_settings.clone(), rx).await.unwrap();
-    
+        let mut server = Server::builder()
+            .settings(initial_settings.clone())
+            .signals(rx)
+            .bind()
+            .await
+            .unwrap();
+
         // Send the reload signal
         tx.send(ShutdownSignal::ReloadConfig).await.unwrap();
-    
+
         // Create new settings to simulate a reload
         let reloaded_settings = Arc::new(Settings {
             hostname: "127.0.0.1".to_string(),
             port: "1234".to_string(), // Change some settings to test reload
             buffer_size: 2048,
+            ..Settings::default()
         });
-    
+
         // Call the method to reload settings
-        server.reload_server(reloaded_settings.clone()).await;
-    
+        server
+            .reload_server(reloaded_settings.clone())
+            .await
+            .unwrap();
+
         // Assert that settings were reloaded
         assert_eq!(server.settings.port, reloaded_settings.port);
         assert_eq!(server.settings.buffer_size, reloaded_settings.buffer_size);
-    
+    }
+
+    #[tokio::test]
+    async fn test_embedded_server_with_shutdown_future() {
+        let router = Router::new().get("/hello", |_: ParsedRequest| async {
+            HTTPResponse::builder()
+                .body(HTTPBody::text("embedded"))
+                .build()
+        });
+        let (stop_tx, stop_rx) = oneshot::channel::<()>();
+        let mut server = Server::builder()
+            .settings(Settings {
+                port: "0".to_string(),
+                ..Settings::default()
+            })
+            .router(router)
+            .shutdown(async {
+                stop_rx.await.ok();
+            })
+            .bind()
+            .await
+            .unwrap();
+        let address = server.local_addr().unwrap();
+        let running = tokio::spawn(async move { server.run().await });
+
+        let mut client = TcpStream::connect(address).await.unwrap();
+        client
+            .write_all(b"GET /hello HTTP/1.1\r\nConnection: close\r\n\r\n")
+            .await
+            .unwrap();
+        let mut output = String::new();
+        client.read_to_string(&mut output).await.unwrap();
+        assert!(output.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(output.ends_with("\r\n\r\nembedded"));
+
+        stop_tx.send(()).unwrap();
+        let exit = running.await.unwrap();
+        assert_eq!(exit.reason, ExitReason::Shutdown);
+        assert!(exit.is_success());
+    }
+
+    #[tokio::test]
+    async fn test_run_exits_with_error_code() {
+        let (tx, rx) = mpsc::channel(1);
+        let mut server = Server::builder()
+            .settings(Settings {
+                port: "0".to_string(),
+                ..Settings::default()
+            })
+            .signals(rx)
+            .bind()
+            .await
+            .unwrap();
+        tx.send(ShutdownSignal::ErrorExit(3)).await.unwrap();
+        assert_eq!(
+            server.run().await,
+            ServerExit {
+                reason: ExitReason::Error(3),
+                code: 3,
+                drained: 0,
+                aborted: 0,
+            }
+        );
+
+        // a server whose signal senders are all dropped has nothing left to stop it
+        let (tx, rx) = mpsc::channel(1);
+        let mut server = Server::builder()
+            .settings(Settings {
+                port: "0".to_string(),
+                ..Settings::default()
+            })
+            .signals(rx)
+            .bind()
+            .await
+            .unwrap();
+        drop(tx);
+        let exit = server.run().await;
+        assert_eq!(exit.reason, ExitReason::SignalsClosed);
+        assert_eq!(exit.code, 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_listener_failures_are_fatal() {
+        let mut server = slow_server(Duration::ZERO, Duration::ZERO).await;
+        let reset = io::Error::from(ErrorKind::ConnectionReset);
+        assert_eq!(server.accept_failed(reset).await, None);
+        assert_eq!(server.accept_failures, 0);
+
+        for _ in 1..MAX_ACCEPT_FAILURES {
+            let broken = io::Error::other("listener broken");
+            assert_eq!(server.accept_failed(broken).await, None);
+        }
+        let broken = io::Error::other("listener broken");
+        assert_eq!(
+            server.accept_failed(broken).await,
+            Some(ExitReason::Error(EXIT_LISTENER_FAILED))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_failed_reload_keeps_serving() {
+        let mut server = slow_server(Duration::ZERO, Duration::ZERO).await;
+        let address = server.local_addr().unwrap();
+        let taken = TcpListener::bind("127.0.0.1:0").await.unwrap();
+
+        let unbindable = Arc::new(Settings {
+            port: taken.local_addr().unwrap().port().to_string(),
+            buffer_size: 2048,
+            ..Settings::default()
+        });
+        assert!(server.reload_server(unbindable).await.is_err());
+        assert_eq!(server.local_addr().unwrap(), address);
+        assert_eq!(server.settings.port, "0");
+        assert_eq!(server.settings.buffer_size, 1024);
+
+        // a reload never stops the server, whether it succeeds or not
+        assert_eq!(
+            server
+                .process_shutdown_signal(Some(ShutdownSignal::ReloadConfig))
+                .await,
+            None
+        );
+        assert!(server.local_addr().is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_status_and_maintenance_signals() {
+        let mut server = slow_server(Duration::from_secs(60), Duration::ZERO).await;
+        let address = server.local_addr().unwrap();
+        let _client = accept_client(&mut server, b"").await;
+
+        let status = server.status();
+        assert_eq!(status.address, Some(address));
+        assert_eq!(status.open_connections, 1);
+        assert_eq!(status.accepted_connections, 1);
+        assert!(!status.draining);
+        assert!(status
+            .to_string()
+            .starts_with(&format!("listening on {}, up ", address)));
+
+        // neither signal stops the server
+        assert_eq!(
+            server
+                .process_shutdown_signal(Some(ShutdownSignal::DumpStatus))
+                .await,
+            None
+        );
+        assert_eq!(
+            server
+                .process_shutdown_signal(Some(ShutdownSignal::ReopenLogs))
+                .await,
+            None
+        );
+
+        server.drain().await;
+        let status = server.status();
+        assert_eq!(status.address, None);
+        assert_eq!(status.open_connections, 0);
+        assert!(status.draining);
+        assert!(status.to_string().ends_with(", draining"));
+    }
+
+    #[tokio::test]
+    async fn test_second_shutdown_signal_ends_drain() {
+        let mut server = slow_server(Duration::from_secs(60), Duration::from_secs(60)).await;
+        let (tx, rx) = mpsc::channel(4);
+        server.rx = Some(rx);
+        let mut stuck = accept_client(&mut server, b"GET /slow HTTP/1.1\r\n\r\n").await;
+        time::sleep(Duration::from_millis(50)).await;
+
+        // signals that do not stop the server are still handled while draining
+        tx.send(ShutdownSignal::DumpStatus).await.unwrap();
+        tx.send(ShutdownSignal::ReloadConfig).await.unwrap();
+        tx.send(ShutdownSignal::NormalExit).await.unwrap();
+        let started = Instant::now();
+        let report = server.drain().await;
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert_eq!(
+            report,
+            DrainReport {
+                drained: 0,
+                aborted: 1
+            }
+        );
+
+        let mut output = String::new();
+        stuck.read_to_string(&mut output).await.unwrap();
+        assert!(output.is_empty());
+    }
+
+    async fn slow_server(delay: Duration, drain_timeout: Duration) -> Server {
+        let router = Router::new().get("/slow", move |_: ParsedRequest| async move {
+            time::sleep(delay).await;
+            HTTPResponse::builder().body(HTTPBody::text("done")).build()
+        });
+        Server::builder()
+            .settings(Settings {
+                port: "0".to_string(),
+                drain_timeout,
+                ..Settings::default()
+            })
+            .router(router)
+            .bind()
+            .await
+            .unwrap()
+    }
+
+    async fn accept_client(server: &mut Server, request: &[u8]) -> TcpStream {
+        let mut client = TcpStream::connect(server.local_addr().unwrap())
+            .await
+            .unwrap();
+        client.write_all(request).await.unwrap();
+        let (socket, _) = accept(&server.listener).await.unwrap();
+        server.handle_incoming_connection(socket);
+        client
+    }
+
+    #[tokio::test]
+    async fn test_drain_finishes_requests_in_progress() {
+        let mut server = slow_server(Duration::from_millis(200), Duration::from_secs(5)).await;
+        let mut busy = accept_client(&mut server, b"GET /slow HTTP/1.1\r\n\r\n").await;
+        let mut idle = accept_client(&mut server, b"").await;
+        time::sleep(Duration::from_millis(50)).await;
+
+        let report = server.drain().await;
+        assert_eq!(
+            report,
+            DrainReport {
+                drained: 2,
+                aborted: 0
+            }
+        );
+        assert!(server.local_addr().is_err());
+
+        // the request in progress is answered, and then the connection closes
+        let mut output = String::new();
+        busy.read_to_string(&mut output).await.unwrap();
+        assert!(output.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(output.contains("Connection: close\r\n"));
+        assert!(output.ends_with("\r\n\r\ndone"));
+        output.clear();
+        idle.read_to_string(&mut output).await.unwrap();
+        assert!(output.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_drain_aborts_connections_past_deadline() {
+        let mut server = slow_server(Duration::from_secs(60), Duration::from_millis(100)).await;
+        let mut stuck = accept_client(&mut server, b"GET /slow HTTP/1.1\r\n\r\n").await;
+        time::sleep(Duration::from_millis(50)).await;
+
+        let started = Instant::now();
+        let report = server.drain().await;
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert_eq!(
+            report,
+            DrainReport {
+                drained: 0,
+                aborted: 1
+            }
+        );
+
+        let mut output = String::new();
+        stuck.read_to_string(&mut output).await.unwrap();
+        assert!(output.is_empty());
     }
 }
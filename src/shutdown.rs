@@ -1,48 +1,146 @@
-use tokio::sync::mpsc;
+use std::future;
+use tokio::sync::{mpsc, watch};
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ShutdownSignal {
+    // a second one while the server drains closes the remaining connections right away
     NormalExit,
     ErrorExit(i32),
     ReloadConfig,
+    ReopenLogs,
+    DumpStatus,
 }
 
+// Tells connections that the server is shutting down, they finish the request in progress and
+// then close instead of waiting for another one
+#[derive(Clone)]
+pub struct Draining(watch::Receiver<bool>);
+
+impl Draining {
+    pub fn channel() -> (watch::Sender<bool>, Self) {
+        let (tx, rx) = watch::channel(false);
+        (tx, Draining(rx))
+    }
+
+    // For connections that are not served by a `Server` and so are never drained
+    pub fn never() -> Self {
+        Draining::channel().1
+    }
+
+    pub fn is_set(&self) -> bool {
+        *self.0.borrow()
+    }
+
+    // Completes once draining starts
+    pub async fn wait(&mut self) {
+        if self.0.wait_for(|draining| *draining).await.is_err() {
+            // the sender is gone without ever starting to drain
+            future::pending::<()>().await;
+        }
+    }
+}
+
+// Forwards process signals to the server for as long as it runs: Ctrl-C and SIGTERM shut it
+// down, SIGHUP reloads the configuration, SIGUSR1 reopens the log file and SIGUSR2 prints the
+// server status
 pub async fn handle_shutdown_signals(tx: mpsc::Sender<ShutdownSignal>) {
     // CTRL-C shutdown channel
     let ctrl_c_tx = tx.clone();
     tokio::spawn(async move {
-        tokio::signal::ctrl_c()
-            .await
-            .expect("Failed to listen for ctrl_c");
-        ctrl_c_tx
-            .send(ShutdownSignal::NormalExit)
-            .await
-            .expect("Failed to send shutdown signal");
+        loop {
+            tokio::signal::ctrl_c()
+                .await
+                .expect("Failed to listen for ctrl_c");
+            if ctrl_c_tx.send(ShutdownSignal::NormalExit).await.is_err() {
+                // the server is gone
+                break;
+            }
+        }
     });
 
-    // SIGTERM and SIGHUP handlers for Unix systems
     #[cfg(unix)]
     setup_unix_signal_handlers(tx).await;
 }
 
 #[cfg(unix)]
 async fn setup_unix_signal_handlers(tx: mpsc::Sender<ShutdownSignal>) {
-    use tokio::signal::unix::{signal, SignalKind};
-    // clone channel 1x per shutdown signal
-    let sigterm_tx = tx.clone();
-    let sighup_tx = tx.clone();
+    use tokio::signal::unix::SignalKind;
+
+    for (kind, shutdown_signal) in [
+        (SignalKind::terminate(), ShutdownSignal::NormalExit),
+        (SignalKind::hangup(), ShutdownSignal::ReloadConfig),
+        (SignalKind::user_defined1(), ShutdownSignal::ReopenLogs),
+        (SignalKind::user_defined2(), ShutdownSignal::DumpStatus),
+    ] {
+        // installed right away, so the signal no longer has its default effect from here on
+        let signals = tokio::signal::unix::signal(kind).expect("Failed to set signal handler");
+        forward_signal(signals, shutdown_signal, tx.clone());
+    }
+}
+
+// Deliveries of one kind of signal, None once no more can arrive
+#[cfg(unix)]
+trait SignalEvents: Send + 'static {
+    fn recv(&mut self) -> impl future::Future<Output = Option<()>> + Send;
+}
+
+#[cfg(unix)]
+impl SignalEvents for tokio::signal::unix::Signal {
+    fn recv(&mut self) -> impl future::Future<Output = Option<()>> + Send {
+        tokio::signal::unix::Signal::recv(self)
+    }
+}
+
+// Sends `shutdown_signal` for every event in `signals` until either side is gone
+#[cfg(unix)]
+fn forward_signal<S: SignalEvents>(
+    mut signals: S,
+    shutdown_signal: ShutdownSignal,
+    tx: mpsc::Sender<ShutdownSignal>,
+) {
     tokio::spawn(async move {
-        let mut term_signal =
-            signal(SignalKind::terminate()).expect("Failed to set SIGTERM handler");
-        let mut hup_signal = signal(SignalKind::hangup()).expect("Failed to set SIGHUP handler");
-
-        // if any of the UNIX signals are received then send corresponding shutdown signal via channel
-        tokio::select! {
-            _ = term_signal.recv() => {
-                sigterm_tx.send(ShutdownSignal::NormalExit).await.expect("Failed to send SIGTERM signal");
-            }
-            _ = hup_signal.recv() => {
-                sighup_tx.send(ShutdownSignal::ReloadConfig).await.expect("Failed to send SIGHUP signal");
+        while signals.recv().await.is_some() {
+            if tx.send(shutdown_signal).await.is_err() {
+                break;
             }
         }
     });
 }
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    impl SignalEvents for mpsc::Receiver<()> {
+        fn recv(&mut self) -> impl future::Future<Output = Option<()>> + Send {
+            mpsc::Receiver::recv(self)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_signals_are_forwarded_repeatedly() {
+        let (signals, events) = mpsc::channel(4);
+        let (tx, mut rx) = mpsc::channel(1);
+        forward_signal(events, ShutdownSignal::ReloadConfig, tx);
+
+        for _ in 0..3 {
+            signals.send(()).await.unwrap();
+            assert_eq!(rx.recv().await, Some(ShutdownSignal::ReloadConfig));
+        }
+        // forwarding ends with the signal source
+        drop(signals);
+        assert_eq!(rx.recv().await, None);
+    }
+
+    #[tokio::test]
+    async fn test_forwarding_stops_without_a_server() {
+        let (signals, events) = mpsc::channel(4);
+        let (tx, rx) = mpsc::channel(1);
+        forward_signal(events, ShutdownSignal::ReopenLogs, tx);
+
+        drop(rx);
+        signals.send(()).await.unwrap();
+        // the forwarding task dropped its receiver after failing to send
+        signals.closed().await;
+    }
+}
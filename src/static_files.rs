@@ -0,0 +1,573 @@
+use crate::body::Body;
+use crate::conditional;
+use crate::file;
+use crate::handler::{Handler, HandlerFuture};
+use crate::http::{HTTPBody, HTTPContentType, HTTPStatus};
+use crate::range::{self, ByteRange, Multipart, RangeRequest};
+use crate::request::{ParsedRequest, RequestHeaders};
+use crate::response::HTTPResponse;
+use crate::sandbox::{DotfilePolicy, Sandbox, SymlinkPolicy};
+
+use std::fs::Metadata;
+use std::io::{self, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::Arc;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncSeekExt};
+
+const DEFAULT_PARAM: &str = "path";
+const DEFAULT_INDEX_FILE: &str = "index.html";
+
+// Serves the files below a root directory, e.g.
+// `router.get("/docs/*path", StaticFiles::new("./site").directory_listing(true))`.
+// The file is taken from the route's wildcard parameter, directories are answered with their
+// index file or, when enabled, with a generated listing.
+#[derive(Clone)]
+pub struct StaticFiles {
+    inner: Arc<Options>,
+}
+
+#[derive(Clone)]
+struct Options {
+    sandbox: Sandbox,
+    param: String,
+    index_file: Option<String>,
+    directory_listing: bool,
+}
+
+impl StaticFiles {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        StaticFiles {
+            inner: Arc::new(Options {
+                sandbox: Sandbox::new(root),
+                param: DEFAULT_PARAM.to_string(),
+                index_file: Some(DEFAULT_INDEX_FILE.to_string()),
+                directory_listing: false,
+            }),
+        }
+    }
+
+    // Options to change, copied first when a clone still shares them so the clone keeps its own
+    fn options(&mut self) -> &mut Options {
+        Arc::make_mut(&mut self.inner)
+    }
+
+    // Name of the route parameter holding the path below the root, "path" by default
+    pub fn param(mut self, name: &str) -> Self {
+        self.options().param = name.to_string();
+        self
+    }
+
+    // File served for directory requests, None to never serve one
+    pub fn index_file(mut self, name: Option<&str>) -> Self {
+        self.options().index_file = name.map(str::to_string);
+        self
+    }
+
+    pub fn directory_listing(mut self, enabled: bool) -> Self {
+        self.options().directory_listing = enabled;
+        self
+    }
+
+    // Whether symbolic links below the root are followed, only to targets inside it by default
+    pub fn symlinks(mut self, policy: SymlinkPolicy) -> Self {
+        let options = self.options();
+        options.sandbox = options.sandbox.clone().symlinks(policy);
+        self
+    }
+
+    // Whether files and directories starting with a dot are served and listed, hidden by default
+    pub fn dotfiles(mut self, policy: DotfilePolicy) -> Self {
+        let options = self.options();
+        options.sandbox = options.sandbox.clone().dotfiles(policy);
+        self
+    }
+
+    async fn serve(&self, request: ParsedRequest) -> HTTPResponse {
+        let options = &self.inner;
+        let segments = request.params.segments(&options.param).unwrap_or_default();
+        let segments = || segments.iter().copied();
+        let path = match options.sandbox.resolve_segments(segments()).await {
+            Ok(path) => path,
+            Err(e) => return HTTPResponse::new(e.status()),
+        };
+        let metadata = match tokio::fs::metadata(&path).await {
+            Ok(metadata) => metadata,
+            Err(e) => return error_response(&e),
+        };
+        if !metadata.is_dir() {
+            return file_response(&request.headers, &path, &metadata).await;
+        }
+
+        // relative links in index pages and listings only resolve below a trailing slash
+        let request_path = request.headers.path();
+        if !request_path.ends_with('/') {
+            let location = match request.headers.uri.query() {
+                Some(query) => format!("{}/?{}", request_path, query),
+                None => format!("{}/", request_path),
+            };
+            return HTTPResponse::builder()
+                .status(HTTPStatus::MovedPermanently)
+                .header("Location", &location)
+                .build();
+        }
+
+        if let Some(index_file) = &options.index_file {
+            // the index file may itself be a link, so it goes through the sandbox as well
+            let index_segments = segments().chain([index_file.as_str()]);
+            if let Ok(index_path) = options.sandbox.resolve_segments(index_segments).await {
+                if let Ok(metadata) = tokio::fs::metadata(&index_path).await {
+                    if metadata.is_file() {
+                        return file_response(&request.headers, &index_path, &metadata).await;
+                    }
+                }
+            }
+        }
+        if options.directory_listing {
+            let has_parent = segments().any(|segment| !segment.is_empty());
+            return match listing(&options.sandbox, &path, request_path, has_parent).await {
+                Ok(html) => HTTPResponse::builder()
+                    .body(HTTPBody {
+                        body: html.into(),
+                        content_type: HTTPContentType::Custom(
+                            "text/html; charset=utf-8".to_string(),
+                        ),
+                    })
+                    .build(),
+                Err(e) => error_response(&e),
+            };
+        }
+        HTTPResponse::new(HTTPStatus::NotFound)
+    }
+}
+
+impl Handler for StaticFiles {
+    fn call(&self, request: ParsedRequest) -> HandlerFuture {
+        let files = self.clone();
+        Box::pin(async move { files.serve(request).await })
+    }
+}
+
+fn error_response(error: &io::Error) -> HTTPResponse {
+    HTTPResponse::new(file::error_status(error))
+}
+
+async fn file_response(request: &RequestHeaders, path: &Path, metadata: &Metadata) -> HTTPResponse {
+    let validators = file::validators(metadata);
+    if let Some(response) = conditional::response_for(request, Some(&validators)) {
+        return response;
+    }
+
+    let length = metadata.len();
+    let content_type = file::mime_type(path).to_string();
+    let result = match range::evaluate(request, length, &validators) {
+        RangeRequest::Full => full_body(path)
+            .await
+            .map(|body| (HTTPStatus::Ok, None, content_type, body)),
+        RangeRequest::Partial(ranges) if ranges.len() == 1 => {
+            let content_range = ranges[0].content_range(length);
+            range_body(path, &ranges[0]).await.map(|body| {
+                let status = HTTPStatus::PartialContent;
+                (status, Some(content_range), content_type, body)
+            })
+        }
+        RangeRequest::Partial(ranges) => {
+            let multipart = Multipart::new(&content_type, length);
+            let content_type = multipart.content_type();
+            multipart_body(path, &ranges, multipart)
+                .await
+                .map(|body| (HTTPStatus::PartialContent, None, content_type, body))
+        }
+        RangeRequest::Unsatisfiable => {
+            let mut response = HTTPResponse::builder()
+                .status(HTTPStatus::RangeNotSatisfiable)
+                .header("Content-Range", &format!("bytes */{}", length))
+                .header("Accept-Ranges", "bytes")
+                .build();
+            validators.add_to(&mut response.headers);
+            return response;
+        }
+    };
+    let (status, content_range, content_type, body) = match result {
+        Ok(result) => result,
+        Err(e) => return error_response(&e),
+    };
+
+    let mut response = HTTPResponse::builder()
+        .status(status)
+        .header("Accept-Ranges", "bytes")
+        .body(HTTPBody {
+            body,
+            content_type: HTTPContentType::Custom(content_type),
+        })
+        .build();
+    if let Some(content_range) = content_range {
+        response.headers.insert("Content-Range", &content_range);
+    }
+    validators.add_to(&mut response.headers);
+    response
+}
+
+async fn full_body(path: &Path) -> io::Result<Body> {
+    let file = tokio::fs::File::open(path).await?;
+    Body::file(file).await
+}
+
+async fn open_at(path: &Path, range: &ByteRange) -> io::Result<tokio::fs::File> {
+    let mut file = tokio::fs::File::open(path).await?;
+    file.seek(SeekFrom::Start(range.start)).await?;
+    Ok(file)
+}
+
+async fn range_body(path: &Path, range: &ByteRange) -> io::Result<Body> {
+    let file = open_at(path, range).await?;
+    Ok(Body::File {
+        file,
+        length: range.length(),
+    })
+}
+
+// Every range is read from its own handle of the file, between the multipart delimiters
+async fn multipart_body(
+    path: &Path,
+    ranges: &[ByteRange],
+    multipart: Multipart,
+) -> io::Result<Body> {
+    let length = multipart.body_length(ranges);
+    let mut reader: Pin<Box<dyn AsyncRead + Send>> = Box::pin(io::Cursor::new(Vec::new()));
+    for range in ranges {
+        let part_head = io::Cursor::new(multipart.part_head(range).into_bytes());
+        let content = open_at(path, range).await?.take(range.length());
+        reader = Box::pin(reader.chain(part_head).chain(content));
+    }
+    let closing = io::Cursor::new(multipart.closing().into_bytes());
+    reader = Box::pin(reader.chain(closing));
+
+    Ok(Body::Stream {
+        reader,
+        length: Some(length),
+    })
+}
+
+// HTML page linking to the entries of a directory, subdirectories first
+async fn listing(
+    sandbox: &Sandbox,
+    directory: &Path,
+    request_path: &str,
+    has_parent: bool,
+) -> io::Result<String> {
+    let mut entries = Vec::new();
+    let mut reader = tokio::fs::read_dir(directory).await?;
+    while let Some(entry) = reader.next_entry().await? {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if sandbox.is_hidden(&name) {
+            continue;
+        }
+        let is_dir = entry.file_type().await?.is_dir();
+        entries.push((!is_dir, name));
+    }
+    entries.sort();
+
+    let title = escape_html(request_path);
+    let mut html = format!(
+        "<!DOCTYPE html>\n<html>\n<head><meta charset=\"utf-8\"><title>Index of {0}</title></head>\n<body>\n<h1>Index of {0}</h1>\n<ul>\n",
+        title
+    );
+    if has_parent {
+        html.push_str("<li><a href=\"../\">../</a></li>\n");
+    }
+    for (is_file, name) in entries {
+        let suffix = if is_file { "" } else { "/" };
+        html.push_str(&format!(
+            "<li><a href=\"{}{}\">{}{}</a></li>\n",
+            encode_segment(&name),
+            suffix,
+            escape_html(&name),
+            suffix
+        ));
+    }
+    html.push_str("</ul>\n</body>\n</html>\n");
+    Ok(html)
+}
+
+fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+// Percent-encode everything but unreserved characters so a file name is a single path segment
+fn encode_segment(name: &str) -> String {
+    let mut encoded = String::with_capacity(name.len());
+    for byte in name.bytes() {
+        if byte.is_ascii_alphanumeric() || b"-._~".contains(&byte) {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::request::parse_stream;
+    use crate::router::Router;
+    use tempfile::TempDir;
+
+    // Fresh directory with a few files, removed again when the guard is dropped
+    fn temp_root() -> TempDir {
+        let temp = tempfile::tempdir().unwrap();
+        let root = temp.path();
+        std::fs::create_dir_all(root.join("docs/sub")).unwrap();
+        std::fs::write(root.join("style.css"), "body {}").unwrap();
+        std::fs::write(root.join("image.png"), [0x89, 0x50, 0x4e, 0x47]).unwrap();
+        std::fs::write(root.join("docs/index.html"), "<h1>docs</h1>").unwrap();
+        std::fs::write(root.join("docs/sub/a <b>.txt"), "a").unwrap();
+        temp
+    }
+
+    async fn get(router: &Router, path: &str) -> HTTPResponse {
+        get_with(router, path, "").await
+    }
+
+    async fn get_with(router: &Router, path: &str, headers: &str) -> HTTPResponse {
+        let raw = format!("GET {} HTTP/1.1\r\n{}\r\n", path, headers);
+        router
+            .handle(parse_stream(&mut raw.as_bytes()).await.unwrap())
+            .await
+    }
+
+    async fn body_text(response: HTTPResponse) -> String {
+        let mut output = Vec::new();
+        response.write_to(&mut output).await.unwrap();
+        let output = String::from_utf8(output).unwrap();
+        output.split_once("\r\n\r\n").unwrap().1.to_string()
+    }
+
+    #[tokio::test]
+    async fn test_serves_files_with_mime_type_and_validators() {
+        let temp = temp_root();
+        let root = temp.path();
+        let router = Router::new().get("/static/*path", StaticFiles::new(root));
+
+        let response = get(&router, "/static/style.css").await;
+        assert_eq!(response.status, HTTPStatus::Ok);
+        assert!(response
+            .head()
+            .contains("Content-Type: text/css; charset=utf-8\r\n"));
+        assert!(response.head().contains("Content-Length: 7\r\n"));
+        assert!(response.headers.get("ETag").unwrap().contains("\"7-"));
+        assert!(response
+            .headers
+            .get("Last-Modified")
+            .unwrap()
+            .ends_with(" GMT"));
+        assert_eq!(body_text(response).await, "body {}");
+
+        let response = get(&router, "/static/image.png").await;
+        assert!(response.head().contains("Content-Type: image/png\r\n"));
+
+        let response = get(&router, "/static/missing.txt").await;
+        assert_eq!(response.status, HTTPStatus::NotFound);
+
+        let response = get(&router, "/static/../secret").await;
+        assert_eq!(response.status, HTTPStatus::BadRequest);
+    }
+
+    #[tokio::test]
+    async fn test_directories() {
+        let temp = temp_root();
+        let root = temp.path();
+        let router = Router::new().get("/static/*path", StaticFiles::new(root));
+
+        let response = get(&router, "/static/docs").await;
+        assert_eq!(response.status, HTTPStatus::MovedPermanently);
+        assert_eq!(response.headers.get("Location"), Some("/static/docs/"));
+
+        let response = get(&router, "/static/docs/").await;
+        assert!(response.head().contains("Content-Type: text/html"));
+        assert_eq!(body_text(response).await, "<h1>docs</h1>");
+
+        // no index file and listings are off by default
+        let response = get(&router, "/static/docs/sub/").await;
+        assert_eq!(response.status, HTTPStatus::NotFound);
+    }
+
+    #[tokio::test]
+    async fn test_directory_listing() {
+        let temp = temp_root();
+        let root = temp.path();
+        let router = Router::new().get(
+            "/static/*path",
+            StaticFiles::new(root)
+                .index_file(None)
+                .directory_listing(true),
+        );
+
+        let listing = body_text(get(&router, "/static/").await).await;
+        assert!(listing.contains("<title>Index of /static/</title>"));
+        assert!(!listing.contains("href=\"../\""));
+        // directories come first
+        let docs = listing.find("href=\"docs/\"").unwrap();
+        let css = listing.find("href=\"style.css\"").unwrap();
+        assert!(docs < css);
+
+        let listing = body_text(get(&router, "/static/docs/sub/").await).await;
+        assert!(listing.contains("href=\"../\""));
+        assert!(listing.contains("<a href=\"a%20%3Cb%3E.txt\">a &lt;b&gt;.txt</a>"));
+    }
+
+    #[tokio::test]
+    async fn test_configuring_a_clone() {
+        let temp = temp_root();
+        let root = temp.path();
+        let files = StaticFiles::new(root).index_file(None);
+        let router = Router::new()
+            .get("/plain/*path", files.clone())
+            .get("/listed/*path", files.directory_listing(true));
+
+        // the clone that was routed first keeps its own options
+        let response = get(&router, "/plain/").await;
+        assert_eq!(response.status, HTTPStatus::NotFound);
+        assert!(body_text(get(&router, "/listed/").await)
+            .await
+            .contains("href=\"docs/\""));
+    }
+
+    #[tokio::test]
+    async fn test_dotfiles() {
+        let temp = temp_root();
+        let root = temp.path();
+        std::fs::write(root.join(".env"), "SECRET=1").unwrap();
+        let hidden = Router::new().get(
+            "/static/*path",
+            StaticFiles::new(root).directory_listing(true),
+        );
+        let response = get(&hidden, "/static/.env").await;
+        assert_eq!(response.status, HTTPStatus::NotFound);
+        let response = get(&hidden, "/static/%2eenv").await;
+        assert_eq!(response.status, HTTPStatus::NotFound);
+        assert!(!body_text(get(&hidden, "/static/").await)
+            .await
+            .contains(".env"));
+
+        let allowed = Router::new().get(
+            "/static/*path",
+            StaticFiles::new(root)
+                .dotfiles(DotfilePolicy::Allow)
+                .directory_listing(true),
+        );
+        assert_eq!(
+            body_text(get(&allowed, "/static/.env").await).await,
+            "SECRET=1"
+        );
+        assert!(body_text(get(&allowed, "/static/").await)
+            .await
+            .contains("href=\".env\""));
+
+        let response = get(&allowed, "/static/docs/..%2f..%2f.env").await;
+        assert_eq!(response.status, HTTPStatus::BadRequest);
+    }
+
+    #[tokio::test]
+    async fn test_conditional_get() {
+        let temp = temp_root();
+        let root = temp.path();
+        let router = Router::new().get("/static/*path", StaticFiles::new(root));
+
+        let response = get(&router, "/static/style.css").await;
+        let etag = response.headers.get("ETag").unwrap().to_string();
+        let last_modified = response.headers.get("Last-Modified").unwrap().to_string();
+
+        let headers = format!("If-None-Match: {}\r\n", etag);
+        let response = get_with(&router, "/static/style.css", &headers).await;
+        assert_eq!(response.status, HTTPStatus::NotModified);
+        assert_eq!(response.headers.get("ETag"), Some(etag.as_str()));
+        assert!(response.body.is_none());
+
+        let headers = format!("If-Modified-Since: {}\r\n", last_modified);
+        let response = get_with(&router, "/static/style.css", &headers).await;
+        assert_eq!(response.status, HTTPStatus::NotModified);
+
+        let headers = "If-None-Match: \"other\"\r\n";
+        let response = get_with(&router, "/static/style.css", headers).await;
+        assert_eq!(response.status, HTTPStatus::Ok);
+    }
+
+    #[tokio::test]
+    async fn test_range_requests() {
+        let temp = temp_root();
+        let root = temp.path();
+        std::fs::write(root.join("digits.txt"), "0123456789").unwrap();
+        let router = Router::new().get("/static/*path", StaticFiles::new(root));
+
+        let response = get(&router, "/static/digits.txt").await;
+        assert_eq!(response.headers.get("Accept-Ranges"), Some("bytes"));
+
+        let response = get_with(&router, "/static/digits.txt", "Range: bytes=2-4\r\n").await;
+        assert_eq!(response.status, HTTPStatus::PartialContent);
+        assert_eq!(response.headers.get("Content-Range"), Some("bytes 2-4/10"));
+        assert!(response.head().contains("Content-Length: 3\r\n"));
+        assert_eq!(body_text(response).await, "234");
+
+        let response = get_with(&router, "/static/digits.txt", "Range: bytes=-3\r\n").await;
+        assert_eq!(body_text(response).await, "789");
+
+        let response = get_with(&router, "/static/digits.txt", "Range: bytes=10-\r\n").await;
+        assert_eq!(response.status, HTTPStatus::RangeNotSatisfiable);
+        assert_eq!(response.headers.get("Content-Range"), Some("bytes */10"));
+
+        // a stale If-Range turns the request into a plain GET
+        let headers = "Range: bytes=0-1\r\nIf-Range: \"stale\"\r\n";
+        let response = get_with(&router, "/static/digits.txt", headers).await;
+        assert_eq!(response.status, HTTPStatus::Ok);
+        assert_eq!(body_text(response).await, "0123456789");
+    }
+
+    #[tokio::test]
+    async fn test_multipart_range_request() {
+        let temp = temp_root();
+        let root = temp.path();
+        std::fs::write(root.join("digits.txt"), "0123456789").unwrap();
+        let router = Router::new().get("/static/*path", StaticFiles::new(root));
+
+        let headers = "Range: bytes=0-1,-2\r\n";
+        let response = get_with(&router, "/static/digits.txt", headers).await;
+        assert_eq!(response.status, HTTPStatus::PartialContent);
+        assert!(response.headers.get("Content-Range").is_none());
+
+        let mut output = Vec::new();
+        response.write_to(&mut output).await.unwrap();
+        let output = String::from_utf8(output).unwrap();
+        let (head, body) = output.split_once("\r\n\r\n").unwrap();
+
+        let boundary = head
+            .split("boundary=")
+            .nth(1)
+            .unwrap()
+            .split("\r\n")
+            .next()
+            .unwrap();
+        assert!(head.ends_with(&format!("Content-Length: {}", body.len())));
+        assert_eq!(
+            body,
+            format!(
+                "\r\n--{0}\r\nContent-Type: text/plain; charset=utf-8\r\nContent-Range: bytes 0-1/10\r\n\r\n01\
+                 \r\n--{0}\r\nContent-Type: text/plain; charset=utf-8\r\nContent-Range: bytes 8-9/10\r\n\r\n89\
+                 \r\n--{0}--\r\n",
+                boundary
+            )
+        );
+    }
+}
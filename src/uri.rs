@@ -0,0 +1,307 @@
+use crate::http::percent_decode;
+use crate::parser::ParseError;
+
+use std::fmt;
+use std::str::FromStr;
+
+// The forms a request target can take (RFC 9112 section 3.2), authority-form is only used by
+// CONNECT which the server does not support
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TargetForm {
+    // `/path?query`, the usual form
+    Origin,
+    // `http://host/path?query`, sent to proxies
+    Absolute,
+    // `*`, only for server-wide OPTIONS requests
+    Asterisk,
+}
+
+// Request target split into its parts. The path is kept percent-encoded as received and also
+// decoded into segments, which is what routing and file lookups use.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Uri {
+    form: TargetForm,
+    scheme: Option<String>,
+    authority: Option<String>,
+    path: String,
+    query: Option<String>,
+    segments: Vec<String>,
+    params: Query,
+}
+
+impl Uri {
+    // Parse a request target, a fragment is dropped since it never belongs to the request.
+    // Fails when escapes in the path are malformed or do not decode to UTF-8.
+    pub fn parse(target: &str) -> Result<Uri, ParseError> {
+        let target = target.split_once('#').map_or(target, |(target, _)| target);
+        if target == "*" {
+            return Ok(Uri {
+                form: TargetForm::Asterisk,
+                scheme: None,
+                authority: None,
+                path: target.to_string(),
+                query: None,
+                segments: Vec::new(),
+                params: Query::default(),
+            });
+        }
+
+        let (form, scheme, authority, rest) = if target.starts_with('/') {
+            (TargetForm::Origin, None, None, target)
+        } else {
+            let (scheme, rest) = target.split_once("://").ok_or(ParseError::InvalidTarget)?;
+            if !scheme.eq_ignore_ascii_case("http") && !scheme.eq_ignore_ascii_case("https") {
+                return Err(ParseError::InvalidTarget);
+            }
+            let end = rest.find(['/', '?']).unwrap_or(rest.len());
+            let (authority, rest) = rest.split_at(end);
+            if authority.is_empty() {
+                return Err(ParseError::InvalidTarget);
+            }
+            let scheme = scheme.to_ascii_lowercase();
+            (
+                TargetForm::Absolute,
+                Some(scheme),
+                Some(authority.to_string()),
+                rest,
+            )
+        };
+
+        let (path, query) = match rest.split_once('?') {
+            Some((path, query)) => (path, Some(query)),
+            None => (rest, None),
+        };
+        // an absolute-form target may leave out the path entirely
+        let path = if path.is_empty() { "/" } else { path };
+        let segments = path[1..]
+            .split('/')
+            .map(|segment| {
+                let decoded = percent_decode(segment).ok_or(ParseError::InvalidTarget)?;
+                String::from_utf8(decoded).map_err(|_| ParseError::InvalidTarget)
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(Uri {
+            form,
+            scheme,
+            authority,
+            path: path.to_string(),
+            query: query.map(str::to_string),
+            segments,
+            params: query.map(Query::parse).unwrap_or_default(),
+        })
+    }
+
+    pub fn form(&self) -> TargetForm {
+        self.form
+    }
+
+    pub fn is_asterisk(&self) -> bool {
+        self.form == TargetForm::Asterisk
+    }
+
+    pub fn scheme(&self) -> Option<&str> {
+        self.scheme.as_deref()
+    }
+
+    // Host and port of an absolute-form target
+    pub fn authority(&self) -> Option<&str> {
+        self.authority.as_deref()
+    }
+
+    // Path as received, still percent-encoded and without the query
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    // Decoded segments of the path, `/a/b/` gives "a", "b" and "". A decoded segment may
+    // contain a `/` that was sent encoded.
+    pub fn segments(&self) -> &[String] {
+        &self.segments
+    }
+
+    // Query string as received, without the leading `?`
+    pub fn query(&self) -> Option<&str> {
+        self.query.as_deref()
+    }
+
+    pub fn query_params(&self) -> &Query {
+        &self.params
+    }
+}
+
+impl FromStr for Uri {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Uri::parse(s)
+    }
+}
+
+// Origin-form of the target, which is what the request is about on this server
+impl fmt::Display for Uri {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.path)?;
+        if let Some(query) = &self.query {
+            write!(f, "?{}", query)?;
+        }
+        Ok(())
+    }
+}
+
+// Decoded `name=value` pairs of a query string in the order they were sent. A key may occur
+// several times, e.g. `?tag=a&tag=b`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Query {
+    entries: Vec<(String, String)>,
+}
+
+impl Query {
+    // Parse an `application/x-www-form-urlencoded` string. `+` stands for a space and
+    // a name or value with malformed escapes is kept as sent rather than failing the request.
+    pub fn parse(query: &str) -> Self {
+        let entries = query
+            .split('&')
+            .filter(|pair| !pair.is_empty())
+            .map(|pair| {
+                let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+                (decode_component(name), decode_component(value))
+            })
+            .collect();
+        Query { entries }
+    }
+
+    // First value of `name`
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.entries
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn get_all<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> {
+        self.entries
+            .iter()
+            .filter(move |(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    // First value of `name` converted to `T`, None if it is missing or does not parse
+    pub fn parse_value<T: FromStr>(&self, name: &str) -> Option<T> {
+        self.get(name).and_then(|value| value.parse().ok())
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.get(name).is_some()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.entries
+            .iter()
+            .map(|(key, value)| (key.as_str(), value.as_str()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+fn decode_component(component: &str) -> String {
+    let component = component.replace('+', " ");
+    match percent_decode(&component) {
+        Some(decoded) => String::from_utf8_lossy(&decoded).into_owned(),
+        None => component,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_origin_form() {
+        let uri = Uri::parse("/echo/hello%20world?download=1#top").unwrap();
+        assert_eq!(uri.form(), TargetForm::Origin);
+        assert_eq!(uri.path(), "/echo/hello%20world");
+        assert_eq!(uri.segments(), ["echo", "hello world"]);
+        assert_eq!(uri.query(), Some("download=1"));
+        assert_eq!(uri.authority(), None);
+        assert_eq!(uri.to_string(), "/echo/hello%20world?download=1");
+
+        assert_eq!(Uri::parse("/").unwrap().segments(), [""]);
+        assert_eq!(Uri::parse("/a/b/").unwrap().segments(), ["a", "b", ""]);
+        // an encoded slash stays inside its segment
+        assert_eq!(Uri::parse("/a%2Fb").unwrap().segments(), ["a/b"]);
+        assert_eq!(Uri::parse("/caf%C3%A9").unwrap().segments(), ["café"]);
+        assert_eq!(Uri::parse("/a?").unwrap().query(), Some(""));
+    }
+
+    #[test]
+    fn test_absolute_form() {
+        let uri = Uri::parse("http://example.com:8080/files/a.txt?x=1").unwrap();
+        assert_eq!(uri.form(), TargetForm::Absolute);
+        assert_eq!(uri.scheme(), Some("http"));
+        assert_eq!(uri.authority(), Some("example.com:8080"));
+        assert_eq!(uri.path(), "/files/a.txt");
+        assert_eq!(uri.query_params().get("x"), Some("1"));
+        assert_eq!(uri.to_string(), "/files/a.txt?x=1");
+
+        let uri = Uri::parse("HTTPS://example.com").unwrap();
+        assert_eq!(uri.scheme(), Some("https"));
+        assert_eq!(uri.path(), "/");
+        let uri = Uri::parse("http://example.com?x").unwrap();
+        assert_eq!(uri.path(), "/");
+        assert_eq!(uri.query(), Some("x"));
+    }
+
+    #[test]
+    fn test_asterisk_form() {
+        let uri = Uri::parse("*").unwrap();
+        assert!(uri.is_asterisk());
+        assert!(uri.segments().is_empty());
+        assert_eq!(uri.to_string(), "*");
+    }
+
+    #[test]
+    fn test_invalid_targets() {
+        for target in [
+            "",
+            "files/a.txt",
+            "example.com:443",
+            "ftp://example.com/",
+            "http:///path",
+            "/a%zz",
+            "/a%",
+            "/%ff",
+        ] {
+            assert_eq!(
+                Uri::parse(target),
+                Err(ParseError::InvalidTarget),
+                "{}",
+                target
+            );
+        }
+    }
+
+    #[test]
+    fn test_query() {
+        let query = Query::parse("tag=a&tag=b&name=J%C3%BCrgen+Doe&flag&&empty=&bad=%zz");
+        assert_eq!(query.get("tag"), Some("a"));
+        assert_eq!(query.get_all("tag").collect::<Vec<_>>(), ["a", "b"]);
+        assert_eq!(query.get("name"), Some("Jürgen Doe"));
+        assert_eq!(query.get("flag"), Some(""));
+        assert!(query.contains("empty"));
+        assert_eq!(query.get("bad"), Some("%zz"));
+        assert_eq!(query.get("missing"), None);
+        assert_eq!(query.len(), 6);
+
+        let query = Query::parse("page=3&a%3Db=c%26d");
+        assert_eq!(query.parse_value::<u32>("page"), Some(3));
+        assert_eq!(query.parse_value::<u32>("a=b"), None);
+        assert_eq!(query.get("a=b"), Some("c&d"));
+    }
+}